@@ -1,22 +1,134 @@
 //! Abstract syntax tree for a Rhiz file.
+use std::sync::Arc;
+
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 
 use crate::parser::{RhizParser, Rule};
 
+/// The name and contents of a parsed Rhizfile.
+#[derive(Debug, Default)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+/// Where a value was written in its Rhizfile.
+#[derive(Clone, Default)]
+pub struct Position {
+    source: Arc<Source>,
+    /// 1-based line number of the value's first character.
+    pub line: usize,
+    /// 1-based column of the value's first character.
+    pub column: usize,
+    start: usize,
+    end: usize,
+}
+
+impl Position {
+    fn from_span(source: &Arc<Source>, span: pest::Span) -> Position {
+        let (line, column) = span.start_pos().line_col();
+        Position {
+            source: Arc::clone(source),
+            line,
+            column,
+            start: span.start(),
+            end: span.end(),
+        }
+    }
+
+    /// The name of the file this position is in.
+    pub fn file(&self) -> &str {
+        &self.source.name
+    }
+
+    /// Render the position's source line with the value underlined, e.g.
+    ///
+    /// ```text
+    ///  --> Rhizfile:3:3
+    ///   |
+    /// 3 |   (copy "a")
+    ///   |   ^^^^^^^^^^
+    /// ```
+    pub fn snippet(&self) -> String {
+        let text = &self.source.text;
+        let line_start = text[..self.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[self.start..]
+            .find('\n')
+            .map_or(text.len(), |i| self.start + i);
+        let line_text = text[line_start..line_end].trim_end_matches('\r');
+        let indent = text[line_start..self.start].chars().count();
+        let width = text[self.start..self.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+        let gutter = " ".repeat(self.line.to_string().len());
+        format!(
+            "{}--> {}\n{} |\n{} | {}\n{} | {}{}",
+            gutter,
+            self,
+            gutter,
+            self.line,
+            line_text,
+            gutter,
+            " ".repeat(indent),
+            "^".repeat(width)
+        )
+    }
+
+    /// Attach this position's snippet to an error message.
+    pub fn annotate(&self, msg: &str) -> String {
+        format!("{}\n{}", msg, self.snippet())
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.source.name, self.line, self.column)
+    }
+}
+
+impl std::fmt::Debug for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
 /// Elements of a Rhizfile.
-#[derive(Debug, PartialEq)]
+///
+/// Values compare equal when they have the same contents, wherever they were
+/// written.
+#[derive(Debug, Clone)]
 pub enum RhizValue {
-    Program(Vec<RhizValue>),
-    SExpr(Vec<RhizValue>),
-    String(String),
+    Program(Vec<RhizValue>, Position),
+    SExpr(Vec<RhizValue>, Position),
+    String(String, Position),
+}
+
+impl RhizValue {
+    pub fn position(&self) -> &Position {
+        match self {
+            RhizValue::Program(_, p) | RhizValue::SExpr(_, p) | RhizValue::String(_, p) => p,
+        }
+    }
+}
+
+impl PartialEq for RhizValue {
+    fn eq(&self, other: &RhizValue) -> bool {
+        match (self, other) {
+            (RhizValue::Program(a, _), RhizValue::Program(b, _)) => a == b,
+            (RhizValue::SExpr(a, _), RhizValue::SExpr(b, _)) => a == b,
+            (RhizValue::String(a, _), RhizValue::String(b, _)) => a == b,
+            _ => false,
+        }
+    }
 }
 
 impl std::convert::From<&RhizValue> for String {
     fn from(v: &RhizValue) -> String {
         match v {
-            RhizValue::String(s) => format!("\"{}\"", s),
-            RhizValue::SExpr(contents) => {
+            RhizValue::String(s, _) => format!("\"{}\"", s),
+            RhizValue::SExpr(contents, _) => {
                 let mut outp = String::new();
                 let items = contents.iter();
                 for i in items {
@@ -26,7 +138,7 @@ impl std::convert::From<&RhizValue> for String {
                 }
                 outp
             }
-            RhizValue::Program(sexprs) => {
+            RhizValue::Program(sexprs, _) => {
                 let mut outp = String::new();
                 let items = sexprs.iter();
                 for i in items {
@@ -41,10 +153,13 @@ impl std::convert::From<&RhizValue> for String {
 }
 
 /// Convert
-fn collect_or_first_error(pairs: Pairs<Rule>) -> Result<Vec<RhizValue>, String> {
+fn collect_or_first_error(
+    pairs: Pairs<Rule>,
+    source: &Arc<Source>,
+) -> Result<Vec<RhizValue>, String> {
     let mut result = Vec::new();
     for p in pairs {
-        match parse_value(p) {
+        match parse_value(p, source) {
             Ok(v) => result.push(v),
             Err(e) => return Err(e),
         }
@@ -53,56 +168,97 @@ fn collect_or_first_error(pairs: Pairs<Rule>) -> Result<Vec<RhizValue>, String>
 }
 
 /// Extract a (possibly nested) `RhizValue` from a Pest parser pair.
-fn parse_value(pair: Pair<Rule>) -> Result<RhizValue, String> {
+fn parse_value(pair: Pair<Rule>, source: &Arc<Source>) -> Result<RhizValue, String> {
+    let pos = Position::from_span(source, pair.as_span());
     match pair.as_rule() {
         Rule::program => {
-            let exprs = collect_or_first_error(pair.into_inner())?;
-            Ok(RhizValue::Program(exprs))
+            let exprs = collect_or_first_error(pair.into_inner(), source)?;
+            Ok(RhizValue::Program(exprs, pos))
         }
         Rule::sexpr => {
-            let exprs = collect_or_first_error(pair.into_inner())?;
-            Ok(RhizValue::SExpr(exprs))
+            let exprs = collect_or_first_error(pair.into_inner(), source)?;
+            Ok(RhizValue::SExpr(exprs, pos))
         }
         Rule::symbol => {
             let raw = pair.as_str().to_owned();
-            Ok(RhizValue::String(raw))
+            Ok(RhizValue::String(raw, pos))
         }
         Rule::string => {
             let raw = pair.as_str();
             // Drop opening and closing " from string source
             let contents = raw[1..raw.len() - 1].to_owned();
-            Ok(RhizValue::String(contents))
+            Ok(RhizValue::String(contents, pos))
         }
         _ => unreachable!("{:?}", pair),
     }
 }
 
 pub fn parse_rhiz_program(src: &str) -> Result<RhizValue, String> {
-    let mut parse_tree =
-        RhizParser::parse(Rule::file, src).map_err(|e| format!("Parsing error: {}", e))?;
+    parse_rhiz_source(src, "<string>")
+}
+
+/// Parse a Rhiz program, using `name` for the file name in the positions of
+/// its values.
+pub fn parse_rhiz_source(src: &str, name: &str) -> Result<RhizValue, String> {
+    let mut parse_tree = RhizParser::parse(Rule::file, src)
+        .map_err(|e| format!("Parsing error: {}", e.with_path(name)))?;
     let prog = parse_tree.next().expect("Expected a program");
-    parse_value(prog)
+    let source = Arc::new(Source {
+        name: name.to_owned(),
+        text: src.to_owned(),
+    });
+    parse_value(prog, &source)
 }
 
 #[test]
 fn test_parse_values() {
     let example_src = r#"(Once there was) (a "way" to get "back home")"#;
-    let expected = RhizValue::Program(vec![
-        RhizValue::SExpr(vec![
-            RhizValue::String("Once".to_owned()),
-            RhizValue::String("there".to_owned()),
-            RhizValue::String("was".to_owned()),
-        ]),
-        RhizValue::SExpr(vec![
-            RhizValue::String("a".to_owned()),
-            RhizValue::String("way".to_owned()),
-            RhizValue::String("to".to_owned()),
-            RhizValue::String("get".to_owned()),
-            RhizValue::String("back home".to_owned()),
-        ]),
-    ]);
+    let p = Position::default;
+    let expected = RhizValue::Program(
+        vec![
+            RhizValue::SExpr(
+                vec![
+                    RhizValue::String("Once".to_owned(), p()),
+                    RhizValue::String("there".to_owned(), p()),
+                    RhizValue::String("was".to_owned(), p()),
+                ],
+                p(),
+            ),
+            RhizValue::SExpr(
+                vec![
+                    RhizValue::String("a".to_owned(), p()),
+                    RhizValue::String("way".to_owned(), p()),
+                    RhizValue::String("to".to_owned(), p()),
+                    RhizValue::String("get".to_owned(), p()),
+                    RhizValue::String("back home".to_owned(), p()),
+                ],
+                p(),
+            ),
+        ],
+        p(),
+    );
     debug_assert_eq!(
         parse_rhiz_program(example_src).expect("Failed to prase example program"),
         expected
     );
 }
+
+#[test]
+fn test_positions() {
+    let src = "(task \"a\"\n  (copy \"x\"))";
+    let prog = parse_rhiz_source(src, "Rhizfile").unwrap();
+    let copy = match &prog {
+        RhizValue::Program(sexprs, _) => match &sexprs[0] {
+            RhizValue::SExpr(items, _) => &items[2],
+            _ => panic!("Expected a sexpr"),
+        },
+        _ => panic!("Expected a program"),
+    };
+    let pos = copy.position();
+    assert_eq!((pos.line, pos.column), (2, 3));
+    assert_eq!(pos.to_string(), "Rhizfile:2:3");
+    assert_eq!(
+        pos.snippet(),
+        " --> Rhizfile:2:3\n  |\n2 |   (copy \"x\"))\n  |   ^^^^^^^^^^"
+    );
+}
@@ -1,8 +1,114 @@
 use rhiz::ast;
+use rhiz::cache;
 use rhiz::compiler;
 use rhiz::executor;
 
-type CommandError = executor::ExecutionError;
+// Exit codes for failures other than an external command's, from the range
+// used by sysexits(3) so that they're unlikely to clash with a command's own.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 64;
+const EXIT_PARSE: i32 = 65;
+const EXIT_NO_RHIZFILE: i32 = 66;
+const EXIT_UNKNOWN_TASK: i32 = 67;
+const EXIT_COMPILATION: i32 = 68;
+// Like a shell, for commands which can't be found or can't be run.
+const EXIT_COMMAND_NOT_FOUND: i32 = 127;
+const EXIT_COMMAND_NOT_RUN: i32 = 126;
+// Like timeout(1), for commands and tasks which ran out of time.
+const EXIT_TIMED_OUT: i32 = 124;
+// Like a shell, commands killed by a signal exit with 128 + the signal number.
+const EXIT_SIGNAL_BASE: i32 = 128;
+
+/// Why a `rhiz` command failed.
+#[derive(Debug)]
+enum CommandError {
+    /// The command line was wrong.
+    Usage(String),
+    NoRhizfile,
+    /// A task given on the command line doesn't exist.
+    UnknownTask(String),
+    /// The Rhizfile couldn't be parsed.
+    Parse(String),
+    Compilation(compiler::CompilationError),
+    Execution(executor::ExecutionError),
+    Io(std::io::Error),
+}
+
+impl CommandError {
+    /// The code `rhiz` exits with: a failed external command's own exit
+    /// code, or one of the `EXIT_*` codes.
+    fn exit_code(&self) -> i32 {
+        match self {
+            CommandError::Usage(_) => EXIT_USAGE,
+            CommandError::NoRhizfile => EXIT_NO_RHIZFILE,
+            CommandError::UnknownTask(_) => EXIT_UNKNOWN_TASK,
+            CommandError::Parse(_) => EXIT_PARSE,
+            CommandError::Compilation(_) => EXIT_COMPILATION,
+            CommandError::Execution(e) => execution_exit_code(e),
+            CommandError::Io(_) => EXIT_FAILURE,
+        }
+    }
+}
+
+/// The exit code for a failed execution (for several failures, the first's).
+fn execution_exit_code(error: &executor::ExecutionError) -> i32 {
+    use executor::ErrorKind;
+
+    match error.kind() {
+        ErrorKind::UnknownTask(_) => EXIT_UNKNOWN_TASK,
+        ErrorKind::TaskArguments { .. } => EXIT_USAGE,
+        ErrorKind::CommandFailed {
+            code: Some(code), ..
+        } => *code,
+        ErrorKind::CommandFailed {
+            signal: Some(signal),
+            ..
+        } => EXIT_SIGNAL_BASE + signal,
+        ErrorKind::TimedOut { .. } => EXIT_TIMED_OUT,
+        ErrorKind::Spawn { source, .. } => match source.kind() {
+            std::io::ErrorKind::NotFound => EXIT_COMMAND_NOT_FOUND,
+            _ => EXIT_COMMAND_NOT_RUN,
+        },
+        ErrorKind::Multiple(errors) => match errors.first() {
+            Some(first) => execution_exit_code(first),
+            None => EXIT_FAILURE,
+        },
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// Shows the message, under the Rhizfile snippet it's about if there is one.
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CommandError::Usage(msg) | CommandError::Parse(msg) => write!(f, "{}", msg),
+            CommandError::NoRhizfile => write!(f, "No Rhizfile found"),
+            CommandError::UnknownTask(task) => write!(f, "No such task: '{}'", task),
+            CommandError::Compilation(e) => write!(f, "{:?}", e),
+            CommandError::Execution(e) => write!(f, "{:?}", e),
+            CommandError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<compiler::CompilationError> for CommandError {
+    fn from(error: compiler::CompilationError) -> Self {
+        CommandError::Compilation(error)
+    }
+}
+
+impl From<executor::ExecutionError> for CommandError {
+    fn from(error: executor::ExecutionError) -> Self {
+        CommandError::Execution(error)
+    }
+}
+
+impl From<std::io::Error> for CommandError {
+    fn from(error: std::io::Error) -> Self {
+        CommandError::Io(error)
+    }
+}
+
 type CommandResult<T> = Result<T, CommandError>;
 
 const RHIZFILE_PATTERN: &str = "[Rr]hizfile";
@@ -30,7 +136,7 @@ fn find_rhizfile() -> CommandResult<std::path::PathBuf> {
                     work_dir = p;
                     continue;
                 }
-                None => return Err(CommandError::from("No Rhizfile found")),
+                None => return Err(CommandError::NoRhizfile),
             },
         }
     }
@@ -39,7 +145,7 @@ fn find_rhizfile() -> CommandResult<std::path::PathBuf> {
 fn file_dir(filepath: &std::path::Path) -> CommandResult<&std::path::Path> {
     filepath
         .parent()
-        .ok_or_else(|| CommandError::from("Rhizfile has no parent?"))
+        .ok_or_else(|| CommandError::Usage("Rhizfile has no parent?".to_owned()))
 }
 
 fn print_tasks(
@@ -53,22 +159,321 @@ fn print_tasks(
             None => "",
         };
         println!(" {: <12} :  {}", name, desc);
+        if !task.params.is_empty() {
+            let params: Vec<String> = task
+                .params
+                .iter()
+                .map(|p| match &p.default {
+                    Some(d) => format!("{}={}", p.name, d),
+                    None => p.name.to_owned(),
+                })
+                .collect();
+            println!(" {: <12}    params: {}", "", params.join(" "));
+        }
     }
     Ok(())
 }
 
-fn main() -> CommandResult<()> {
+/// Show or clear the fingerprint cache (`rhiz --cache show|clear`).
+fn cache_command(action: Option<&str>, dir: &std::path::Path) -> CommandResult<()> {
+    match action {
+        Some("show") => {
+            let cache = cache::Cache::load(dir)?;
+            println!("Fingerprints in '{}': ", cache.path().display());
+            for (task, fingerprint) in cache.entries() {
+                println!(" {: <12} :  {}", task, fingerprint);
+            }
+            Ok(())
+        }
+        Some("clear") => {
+            cache::Cache::clear(dir)?;
+            Ok(())
+        }
+        _ => Err(CommandError::Usage(
+            "Usage: rhiz --cache show|clear".to_owned(),
+        )),
+    }
+}
+
+/// What to do, according to the command line.
+struct CommandLine {
+    options: executor::Options,
+    invocations: Vec<executor::Invocation>,
+    /// Re-run the tasks when files change.
+    watch: bool,
+    /// Patterns for the files to watch, instead of the tasks' sources.
+    watch_globs: Vec<String>,
+}
+
+/// Parse the number of jobs given with `-j` or `RHIZ_JOBS` (`source`).
+fn parse_jobs(value: Option<String>, source: &str) -> CommandResult<usize> {
+    match value.map(|v| v.parse::<usize>()) {
+        Some(Ok(jobs)) if jobs > 0 => Ok(jobs),
+        _ => Err(CommandError::Usage(format!(
+            "`{}` needs a positive number of jobs",
+            source
+        ))),
+    }
+}
+
+/// Parse the command line (without the program name): task names, each followed
+/// by the arguments for its parameters, and options (anywhere before `--`).
+fn parse_args<I>(
+    args: I,
+    tasks: &std::collections::HashMap<String, compiler::Task>,
+) -> CommandResult<CommandLine>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut command_line = CommandLine {
+        options: executor::Options::default(),
+        invocations: Vec::new(),
+        watch: false,
+        watch_globs: Vec::new(),
+    };
+    let invocations = &mut command_line.invocations;
+    let mut args = args.into_iter();
+    // After `--`, every word is a value for the last task's parameters.
+    let mut values_only = false;
+    while let Some(arg) = args.next() {
+        if values_only {
+            invocations.last_mut().unwrap().args.push(arg);
+        } else if arg == "--" && !invocations.is_empty() {
+            values_only = true;
+        } else if arg.starts_with('-') {
+            match arg.as_str() {
+                "-j" | "--jobs" => {
+                    command_line.options.jobs = Some(parse_jobs(args.next(), &arg)?);
+                }
+                "--parallel" => command_line.options.parallel = true,
+                "--dry-run" => command_line.options.dry_run = true,
+                // Other tasks carry on; the rest of a failed task doesn't.
+                "--keep-going" => command_line.options.keep_going = true,
+                "--output" => match args.next() {
+                    Some(mode) => {
+                        command_line.options.output = mode.parse().map_err(CommandError::Usage)?
+                    }
+                    None => {
+                        return Err(CommandError::Usage(
+                            "`--output` needs a mode: interleaved, prefix or block".to_owned(),
+                        ))
+                    }
+                },
+                "--shell" => match args.next() {
+                    Some(shell) if !shell.trim().is_empty() => {
+                        command_line.options.shell = Some(shell)
+                    }
+                    _ => {
+                        return Err(CommandError::Usage(
+                            "`--shell` needs a shell, e.g. \"bash -c\"".to_owned(),
+                        ))
+                    }
+                },
+                "--watch" => command_line.watch = true,
+                "--watch-glob" => match args.next() {
+                    Some(pattern) => {
+                        command_line.watch = true;
+                        command_line.watch_globs.push(pattern);
+                    }
+                    None => {
+                        return Err(CommandError::Usage(
+                            "`--watch-glob` needs a pattern".to_owned(),
+                        ))
+                    }
+                },
+                _ => return Err(CommandError::Usage(format!("Unknown option: {}", arg))),
+            }
+        } else if tasks.contains_key(&arg) {
+            if let Some(last) = invocations.last() {
+                if let Some(param) = missing_param(last, &tasks[&last.task]) {
+                    return Err(CommandError::Usage(format!(
+                        "'{}' is a task, but '{}' still needs a value for '{}'; \
+                         to give it '{}', put it after `--`",
+                        arg, last.task, param, arg
+                    )));
+                }
+            }
+            invocations.push(executor::Invocation {
+                task: arg,
+                args: Vec::new(),
+            });
+        } else {
+            match invocations.last_mut() {
+                Some(invocation) => invocation.args.push(arg),
+                None => return Err(CommandError::UnknownTask(arg)),
+            }
+        }
+    }
+    Ok(command_line)
+}
+
+/// The first parameter without a default which an invocation's arguments
+/// (named or by position) don't give a value.
+fn missing_param<'a>(
+    invocation: &executor::Invocation,
+    task: &'a compiler::Task,
+) -> Option<&'a str> {
+    let named: Vec<&str> = invocation
+        .args
+        .iter()
+        .filter_map(|arg| arg.split_once('=').map(|(name, _)| name))
+        .collect();
+    let positional = invocation.args.len() - named.len();
+    task.params
+        .iter()
+        .filter(|p| !named.contains(&p.name.as_str()))
+        .skip(positional)
+        .find(|p| p.default.is_none())
+        .map(|p| p.name.as_str())
+}
+
+/// Run the tasks, then run them again whenever the watched files change.
+fn watch(
+    command_line: &CommandLine,
+    program: &compiler::Program,
+    working_dir: &std::path::Path,
+) -> CommandResult<()> {
+    let patterns = if command_line.watch_globs.is_empty() {
+        executor::source_patterns(&command_line.invocations, program, working_dir)?
+    } else {
+        command_line.watch_globs.clone()
+    };
+    if patterns.is_empty() {
+        return Err(CommandError::Usage(
+            "Nothing to watch: declare the tasks' `sources` or use `--watch-glob`".to_owned(),
+        ));
+    }
+    let mut watcher = rhiz::watch::Watcher::new(working_dir, patterns)?;
+    loop {
+        let result = executor::exec_tasks(
+            &command_line.invocations,
+            program,
+            working_dir,
+            &command_line.options,
+        );
+        if let Err(e) = result {
+            eprintln!("Error: {}", CommandError::from(e));
+        }
+        println!("Watching for changes...");
+        watcher.wait_for_change()?;
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn run() -> CommandResult<()> {
     use std::env;
 
     let rhizfile_path = &find_rhizfile()?;
     let working_dir_path = file_dir(rhizfile_path)?;
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--cache") {
+        return cache_command(args.get(1).map(String::as_str), working_dir_path);
+    }
+
     let src = std::fs::read_to_string(rhizfile_path)?;
-    let parsed = &ast::parse_rhiz_program(&src)?;
-    let tasks = &compiler::compile(parsed)?;
+    let parsed = &ast::parse_rhiz_source(&src, &rhizfile_path.display().to_string())
+        .map_err(CommandError::Parse)?;
+    let program = &compiler::compile(parsed)?;
 
-    match env::args().nth(1) {
-        Some(tname) => executor::exec_task(&tname, tasks, working_dir_path),
-        None => print_tasks(rhizfile_path, tasks),
+    let mut command_line = parse_args(args, &program.tasks)?;
+    if command_line.options.jobs.is_none() {
+        if let Some(jobs) = env::var_os("RHIZ_JOBS") {
+            let jobs = jobs.into_string().ok();
+            command_line.options.jobs = Some(parse_jobs(jobs, "RHIZ_JOBS")?);
+        }
+    }
+    if command_line.options.shell.is_none() {
+        command_line.options.shell = env::var("RHIZ_SHELL").ok().filter(|s| !s.trim().is_empty());
     }
+    if command_line.invocations.is_empty() {
+        print_tasks(rhizfile_path, &program.tasks)
+    } else if command_line.watch {
+        watch(&command_line, program, working_dir_path)
+    } else {
+        executor::exec_tasks(
+            &command_line.invocations,
+            program,
+            working_dir_path,
+            &command_line.options,
+        )?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let src = r#"(task "build") (task "deploy" (params target)) (task "test")"#;
+    let parsed = ast::parse_rhiz_program(src).unwrap();
+    let program = compiler::compile(&parsed).unwrap();
+    let parse = |args: &[&str]| parse_args(args.iter().map(|a| a.to_string()), &program.tasks);
+
+    let command_line = parse(&["--parallel", "build", "deploy", "prod", "test"]).unwrap();
+    assert!(command_line.options.parallel);
+    let parsed: Vec<(&str, &[String])> = command_line
+        .invocations
+        .iter()
+        .map(|i| (i.task.as_str(), i.args.as_slice()))
+        .collect();
+    assert_eq!(
+        parsed,
+        vec![
+            ("build", &[][..]),
+            ("deploy", &["prod".to_owned()][..]),
+            ("test", &[][..])
+        ]
+    );
+
+    let command_line = parse(&["--keep-going", "-j", "4", "build"]).unwrap();
+    assert!(command_line.options.keep_going);
+    assert_eq!(command_line.options.jobs, Some(4));
+
+    let command_line = parse(&["--output", "block", "build"]).unwrap();
+    assert_eq!(command_line.options.output, executor::OutputMode::Block);
+
+    let command_line = parse(&["--shell", "bash -eo pipefail -c", "build"]).unwrap();
+    assert_eq!(
+        command_line.options.shell.as_deref(),
+        Some("bash -eo pipefail -c")
+    );
+
+    let command_line = parse(&["--watch-glob", "src/*.rs", "build"]).unwrap();
+    assert!(command_line.watch);
+    assert_eq!(command_line.watch_globs, vec!["src/*.rs".to_owned()]);
+
+    let command_line = parse(&["build", "deploy", "--", "test", "build"]).unwrap();
+    assert_eq!(command_line.invocations.len(), 2);
+    assert_eq!(
+        command_line.invocations[1].args,
+        vec!["test".to_owned(), "build".to_owned()]
+    );
+
+    // Options can come after the tasks; a value like an option goes after `--`.
+    let command_line = parse(&["deploy", "--dry-run", "prod"]).unwrap();
+    assert!(command_line.options.dry_run);
+    assert_eq!(command_line.invocations[0].args, vec!["prod".to_owned()]);
+    let command_line = parse(&["deploy", "--", "--dry-run"]).unwrap();
+    assert!(!command_line.options.dry_run);
+    assert_eq!(
+        command_line.invocations[0].args,
+        vec!["--dry-run".to_owned()]
+    );
+    assert!(parse(&["deploy", "-x"]).is_err());
+
+    assert!(parse(&["deploy", "target=prod", "test"]).is_ok());
+    assert!(parse(&["deploy", "test"]).is_err());
+    assert!(parse(&["missing"]).is_err());
+    assert!(parse(&["--watch-glob"]).is_err());
+    assert!(parse(&["--output"]).is_err());
+    assert!(parse(&["-j", "0", "build"]).is_err());
+    assert!(parse(&["--output", "fancy", "build"]).is_err());
+    assert!(parse(&["--jobs", "many", "build"]).is_err());
+    assert!(parse(&["--shell", " ", "build"]).is_err());
+    assert!(parse(&["--unknown", "build"]).is_err());
 }
@@ -0,0 +1,162 @@
+//! Fingerprints of the inputs of tasks which declare `(fingerprint)`, stored
+//! between runs so that a task can be skipped when its inputs haven't changed.
+//!
+//! The cache is a text file in the Rhizfile's directory, with a
+//! `task-name<TAB>fingerprint` line for each task.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the cache file, which is kept next to the Rhizfile.
+pub const CACHE_FILE_NAME: &str = ".rhiz-cache";
+
+/// The fingerprints of the tasks' last successful runs.
+pub struct Cache {
+    path: PathBuf,
+    entries: BTreeMap<String, String>,
+}
+
+impl Cache {
+    /// Load the cache for the Rhizfile in `dir`. A missing cache file is an
+    /// empty cache.
+    pub fn load(dir: &Path) -> io::Result<Cache> {
+        let path = dir.join(CACHE_FILE_NAME);
+        let mut entries = BTreeMap::new();
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    if let Some((task, fingerprint)) = line.split_once('\t') {
+                        entries.insert(task.to_owned(), fingerprint.to_owned());
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Cache { path, entries })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut contents = String::new();
+        for (task, fingerprint) in &self.entries {
+            contents.push_str(&format!("{}\t{}\n", task, fingerprint));
+        }
+        fs::write(&self.path, contents)
+    }
+
+    /// Delete the cache file for the Rhizfile in `dir`, if there is one.
+    pub fn clear(dir: &Path) -> io::Result<()> {
+        match fs::remove_file(dir.join(CACHE_FILE_NAME)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, task: &str) -> Option<&str> {
+        self.entries.get(task).map(String::as_str)
+    }
+
+    pub fn set(&mut self, task: &str, fingerprint: String) {
+        self.entries.insert(task.to_owned(), fingerprint);
+    }
+
+    /// Each task's name and fingerprint, sorted by name.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(task, fingerprint)| (task.as_str(), fingerprint.as_str()))
+    }
+}
+
+/// A 64-bit FNV-1a hash, which (unlike `std`'s hashers) is stable between
+/// versions of Rust, so fingerprints stay valid.
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub fn new() -> Fingerprint {
+        Fingerprint(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Add a field, with its length so that adjacent fields can't be confused.
+    pub fn update_field(&mut self, bytes: &[u8]) {
+        self.update(&(bytes.len() as u64).to_le_bytes());
+        self.update(bytes);
+    }
+
+    /// Add a file's contents as a field.
+    pub fn update_file(&mut self, path: &Path) -> io::Result<()> {
+        use std::io::Read;
+
+        let mut file = fs::File::open(path)?;
+        let mut buf = [0; 8192];
+        let mut len: u64 = 0;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            self.update(&buf[..n]);
+            len += n as u64;
+        }
+        self.update(&len.to_le_bytes());
+        Ok(())
+    }
+
+    pub fn finish(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Fingerprint::new()
+    }
+}
+
+#[test]
+fn test_fingerprint() {
+    let mut fp = Fingerprint::new();
+    assert_eq!(fp.finish(), "cbf29ce484222325");
+    fp.update(b"a");
+    assert_eq!(fp.finish(), "af63dc4c8601ec8c");
+
+    let fields = |a: &[u8], b: &[u8]| {
+        let mut fp = Fingerprint::new();
+        fp.update_field(a);
+        fp.update_field(b);
+        fp.finish()
+    };
+    assert_ne!(fields(b"ab", b"c"), fields(b"a", b"bc"));
+}
+
+#[test]
+fn test_cache_round_trip() {
+    let dir = std::env::temp_dir().join("rhiz-test-cache-round-trip");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut cache = Cache::load(&dir).unwrap();
+    assert_eq!(cache.entries().count(), 0);
+    cache.set("build", "0123456789abcdef".to_owned());
+    cache.save().unwrap();
+
+    let cache = Cache::load(&dir).unwrap();
+    assert_eq!(cache.get("build"), Some("0123456789abcdef"));
+
+    Cache::clear(&dir).unwrap();
+    Cache::clear(&dir).unwrap();
+    assert_eq!(Cache::load(&dir).unwrap().get("build"), None);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,54 +1,353 @@
 //! This module contains a struct which holds the information necessary to
 //! execute a Rhiz task and the implementation for creating these structs from a
 //! Rhizfile's AST.
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 use crate::ast;
+use crate::conditions;
+use crate::functions;
+use crate::timeout;
+use crate::variables;
 
-pub type CompilationError = Box<dyn std::error::Error>;
 pub type CompilationResult<T> = Result<T, CompilationError>;
 
+/// The ways a Rhizfile can fail to compile.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// A declaration which isn't of the form it should be.
+    Invalid(String),
+    /// A task needs a task which isn't declared.
+    UnknownTask { task: String, needed: String },
+    /// Tasks which (transitively) need themselves, starting and ending with
+    /// the same task.
+    DependencyCycle(Vec<String>),
+    /// A task declares a parameter twice.
+    DuplicateParam(String),
+    /// A variable is defined twice.
+    DuplicateVariable(String),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Invalid(msg) => write!(f, "{}", msg),
+            ErrorKind::UnknownTask { task, needed } => {
+                write!(f, "Task '{}' needs an unknown task '{}'", task, needed)
+            }
+            ErrorKind::DependencyCycle(cycle) => {
+                write!(f, "Task dependency cycle: {}", cycle.join(" -> "))
+            }
+            ErrorKind::DuplicateParam(name) => {
+                write!(f, "Parameter '{}' is declared twice", name)
+            }
+            ErrorKind::DuplicateVariable(name) => {
+                write!(f, "Variable '{}' is already defined", name)
+            }
+        }
+    }
+}
+
+/// An error in a Rhizfile, and where it is.
+pub struct CompilationError {
+    kind: ErrorKind,
+    position: ast::Position,
+}
+
+impl CompilationError {
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn position(&self) -> &ast::Position {
+        &self.position
+    }
+}
+
+impl fmt::Display for CompilationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.kind)
+    }
+}
+
+/// Shows the error's message under the Rhizfile snippet it's about.
+impl fmt::Debug for CompilationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.position.annotate(&self.kind.to_string()))
+    }
+}
+
+impl std::error::Error for CompilationError {}
+
 /// Compilation target for s-xpressions of the format
 /// ```ignore
-/// (task "name" ["description"] [funcall]*)
+/// (task "name" ["description"] [declaration]* [funcall]*)
 /// ```
+///
+/// where the declarations are `(needs task-name*)`, `(env setting*)`,
+/// `(params param*)`, `(sources pattern*)`, `(outputs pattern*)`,
+/// `(fingerprint)`, `(timeout duration)` and `(finally funcall*)`.
 pub struct Task<'a> {
     pub name: String,
     pub description: Option<String>,
+    /// Parameters which are given values when the task is invoked.
+    pub params: Vec<Param>,
+    /// Tasks which must be run before this one.
+    pub needs: Vec<String>,
+    /// Environment settings (`NAME=value` or `:unset NAME`) for the task's
+    /// external commands.
+    pub env: Vec<&'a ast::RhizValue>,
+    /// Glob patterns for the files the task reads.
+    pub sources: Vec<&'a ast::RhizValue>,
+    /// Glob patterns for the files the task writes. A task with outputs is
+    /// skipped when they're all newer than its sources.
+    pub outputs: Vec<&'a ast::RhizValue>,
+    /// Decide whether the task is up to date from a fingerprint of its
+    /// sources' contents and its body, rather than from timestamps.
+    pub fingerprint: bool,
+    /// How long the task's items can run for (e.g. `10m`); its `finally`
+    /// expressions aren't limited.
+    pub timeout: Option<&'a ast::RhizValue>,
     pub items: Vec<&'a ast::RhizValue>,
+    /// Expressions executed after the task's items, even if one of them
+    /// fails.
+    pub finally: Vec<&'a ast::RhizValue>,
+    /// The task's declaration.
+    pub sexpr: &'a ast::RhizValue,
+}
+
+/// A task parameter, declared as `name` or `(name "default")`.
+pub struct Param {
+    pub name: String,
+    /// The parameter's value if none is given; required if `None`.
+    pub default: Option<String>,
+}
+
+/// Make an error pointing at the value it's about.
+fn error_at(value: &ast::RhizValue, kind: ErrorKind) -> CompilationError {
+    CompilationError {
+        kind,
+        position: value.position().clone(),
+    }
+}
+
+/// Make an `ErrorKind::Invalid` error pointing at the value it's about.
+fn invalid_at(value: &ast::RhizValue, msg: &str) -> CompilationError {
+    error_at(value, ErrorKind::Invalid(msg.to_owned()))
+}
+
+/// Split a `(name args*)` s-expression into its name and arguments.
+pub(crate) fn split_form(sexpr: &ast::RhizValue) -> Option<(&str, &[ast::RhizValue])> {
+    match sexpr {
+        ast::RhizValue::SExpr(items, _) => match items.first() {
+            Some(ast::RhizValue::String(s, _)) => Some((s, &items[1..])),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Get the task names listed in a `(needs ...)` declaration.
+fn compile_needs(args: &[ast::RhizValue]) -> CompilationResult<Vec<String>> {
+    let mut needs = Vec::new();
+    for item in args {
+        match item {
+            ast::RhizValue::String(s, _) => needs.push(s.to_owned()),
+            _ => {
+                let msg = "`needs` should only contain task names";
+                return Err(invalid_at(item, msg));
+            }
+        }
+    }
+    Ok(needs)
+}
+
+/// Get the parameters in a `(params ...)` declaration.
+fn compile_params(args: &[ast::RhizValue]) -> CompilationResult<Vec<Param>> {
+    let mut params = Vec::new();
+    for item in args {
+        let (name, default) = match item {
+            ast::RhizValue::String(s, _) => (s, None),
+            ast::RhizValue::SExpr(parts, _) => match parts.as_slice() {
+                [ast::RhizValue::String(s, _), ast::RhizValue::String(d, _)] => (s, Some(d)),
+                _ => {
+                    let msg = "Parameters with defaults should be of the form (name \"default\")";
+                    return Err(invalid_at(item, msg));
+                }
+            },
+            _ => return Err(invalid_at(item, "Invalid parameter")),
+        };
+        if !variables::is_valid_name(name) {
+            return Err(invalid_at(item, "Invalid parameter name"));
+        }
+        if params.iter().any(|p: &Param| &p.name == name) {
+            let kind = ErrorKind::DuplicateParam(name.to_owned());
+            return Err(error_at(item, kind));
+        }
+        params.push(Param {
+            name: name.to_owned(),
+            default: default.cloned(),
+        });
+    }
+    Ok(params)
+}
+
+/// Check the patterns in a `(sources ...)` or `(outputs ...)` declaration.
+fn compile_patterns<'a>(
+    form: &str,
+    args: &'a [ast::RhizValue],
+) -> CompilationResult<Vec<&'a ast::RhizValue>> {
+    if let Some(v) = args
+        .iter()
+        .find(|v| !matches!(v, ast::RhizValue::String(_, _)))
+    {
+        let msg = format!("`{}` should only contain file patterns", form);
+        return Err(invalid_at(v, &msg));
+    }
+    Ok(args.iter().collect())
+}
+
+/// Check a `(timeout duration)` declaration. A duration which refers to
+/// variables is checked when the task runs.
+fn compile_timeout<'a>(
+    item: &'a ast::RhizValue,
+    args: &'a [ast::RhizValue],
+) -> CompilationResult<&'a ast::RhizValue> {
+    match args {
+        [value @ ast::RhizValue::String(s, _)] => {
+            if !s.contains('$') {
+                timeout::parse_duration(s).map_err(|msg| invalid_at(value, &msg))?;
+            }
+            Ok(value)
+        }
+        _ => Err(invalid_at(
+            item,
+            "`timeout` takes a duration, e.g. (timeout 10m)",
+        )),
+    }
+}
+
+/// Check that the expressions in a form are all s-expressions.
+fn check_sexprs<'v, I>(form: &str, exprs: I) -> CompilationResult<()>
+where
+    I: IntoIterator<Item = &'v ast::RhizValue>,
+{
+    for v in exprs {
+        if !matches!(v, ast::RhizValue::SExpr(_, _)) {
+            let msg = format!("`{}` should only contain SExprs", form);
+            return Err(invalid_at(v, &msg));
+        }
+    }
+    Ok(())
+}
+
+/// Check the control-flow forms in an expression in a task's body (including
+/// those nested in other forms), so that a malformed condition or `try` is
+/// reported before anything runs.
+fn check_control_flow(sexpr: &ast::RhizValue) -> CompilationResult<()> {
+    let (name, args) = match split_form(sexpr) {
+        Some(form) => form,
+        None => return Ok(()),
+    };
+    let body: Vec<&ast::RhizValue> = match name {
+        "if" | "when" | "unless" => {
+            let (valid, shape) = match name {
+                "if" => ((2..=3).contains(&args.len()), "condition then [else]"),
+                _ => (!args.is_empty(), "condition expression*"),
+            };
+            if !valid {
+                let msg = format!("`{}` should be of the form ({} {})", name, name, shape);
+                return Err(invalid_at(sexpr, &msg));
+            }
+            conditions::check(&args[0]).map_err(|(msg, item)| invalid_at(item, &msg))?;
+            check_sexprs(name, &args[1..])?;
+            args[1..].iter().collect()
+        }
+        "try" => {
+            let form =
+                functions::TryForm::split(args).map_err(|(msg, item)| invalid_at(item, msg))?;
+            let exprs: Vec<&ast::RhizValue> = form
+                .body
+                .iter()
+                .chain(form.catch.unwrap_or_default())
+                .chain(form.finally.unwrap_or_default())
+                .collect();
+            check_sexprs(name, exprs.iter().copied())?;
+            exprs
+        }
+        "retry" => {
+            if args.is_empty() {
+                let msg = "`retry` should be of the form (retry attempts [delay] expression*)";
+                return Err(invalid_at(sexpr, msg));
+            }
+            let form =
+                functions::RetryForm::split(args).map_err(|(msg, item)| invalid_at(item, &msg))?;
+            check_sexprs(name, form.body)?;
+            form.body.iter().collect()
+        }
+        "in-dir" => {
+            if !matches!(args.first(), Some(ast::RhizValue::String(_, _))) {
+                let msg = "`in-dir` should be of the form (in-dir \"directory\" expression*)";
+                return Err(invalid_at(sexpr, msg));
+            }
+            check_sexprs(name, &args[1..])?;
+            args[1..].iter().collect()
+        }
+        "ignore-errors" => {
+            check_sexprs(name, args)?;
+            args.iter().collect()
+        }
+        "catch" | "finally" => {
+            let msg = format!("`{}` can only be used at the end of a `try`", name);
+            return Err(invalid_at(sexpr, &msg));
+        }
+        _ => args.iter().collect(),
+    };
+    for item in body {
+        check_control_flow(item)?;
+    }
+    Ok(())
+}
+
+/// Check the settings in an `(env ...)` declaration.
+fn compile_env(args: &[ast::RhizValue]) -> CompilationResult<Vec<&ast::RhizValue>> {
+    let settings: Vec<&ast::RhizValue> = args.iter().collect();
+    variables::parse_env_settings(&settings).map_err(|(msg, item)| invalid_at(item, &msg))?;
+    Ok(settings)
 }
 
 impl<'a> Task<'a> {
     fn compile(sexpr: &'a ast::RhizValue) -> CompilationResult<Task<'a>> {
         let items = match sexpr {
-            ast::RhizValue::SExpr(items) => items,
-            _ => return Err(CompilationError::from("Expected a sexpr to make a task")),
+            ast::RhizValue::SExpr(items, _) => items,
+            _ => return Err(invalid_at(sexpr, "Expected a sexpr to make a task")),
         };
         if items.len() < 2 {
-            return Err(CompilationError::from("Invalid task declaration"));
+            return Err(invalid_at(sexpr, "Invalid task declaration"));
         };
         match &items[0] {
-            ast::RhizValue::String(s) => {
+            ast::RhizValue::String(s, _) => {
                 if s != "task" {
-                    let msg = "Only 'task' declarations allowed at the top-level of a Rhizfile";
-                    return Err(CompilationError::from(msg));
+                    let msg =
+                        "Only 'task' and 'define' declarations allowed at the top-level of a Rhizfile";
+                    return Err(invalid_at(&items[0], msg));
                 }
             }
             _ => {
                 let msg = "Top-level Rhizfile declarations should be of the form (task name [description] [commands]*)";
-                return Err(CompilationError::from(msg));
+                return Err(invalid_at(&items[0], msg));
             }
         }
         let name = match &items[1] {
-            ast::RhizValue::String(s) => s.to_owned(),
+            ast::RhizValue::String(s, _) => s.to_owned(),
             _ => {
                 let msg = "Task names should be strings";
-                return Err(CompilationError::from(msg));
+                return Err(invalid_at(&items[1], msg));
             }
         };
         let description = if items.len() > 2 {
             match &items[2] {
-                ast::RhizValue::String(s) => Some(s.to_owned()),
+                ast::RhizValue::String(s, _) => Some(s.to_owned()),
                 _ => None,
             }
         } else {
@@ -58,33 +357,333 @@ impl<'a> Task<'a> {
             Some(_) => &items[3..],
             None => &items[2..],
         };
-        if rest.iter().any(|v| !matches!(v, ast::RhizValue::SExpr(_))) {
+        if let Some(v) = rest
+            .iter()
+            .find(|v| !matches!(v, ast::RhizValue::SExpr(_, _)))
+        {
             let msg = "Tasks should only contain SExprs";
-            return Err(CompilationError::from(msg));
+            return Err(invalid_at(v, msg));
+        }
+        let mut params = Vec::new();
+        let mut needs = Vec::new();
+        let mut env = Vec::new();
+        let mut sources = Vec::new();
+        let mut outputs = Vec::new();
+        let mut fingerprint = false;
+        let mut timeout = None;
+        let mut body = Vec::new();
+        let mut finally = Vec::new();
+        for item in rest {
+            match split_form(item) {
+                Some(("params", args)) => {
+                    if !params.is_empty() {
+                        return Err(invalid_at(item, "Tasks can only declare `params` once"));
+                    }
+                    params = compile_params(args)?;
+                }
+                Some(("needs", args)) => needs.extend(compile_needs(args)?),
+                Some(("env", args)) => env.extend(compile_env(args)?),
+                Some(("sources", args)) => sources.extend(compile_patterns("sources", args)?),
+                Some(("outputs", args)) => outputs.extend(compile_patterns("outputs", args)?),
+                Some(("fingerprint", args)) => {
+                    if !args.is_empty() {
+                        return Err(invalid_at(item, "`fingerprint` doesn't take arguments"));
+                    }
+                    fingerprint = true;
+                }
+                Some(("timeout", args)) => {
+                    if timeout.is_some() {
+                        return Err(invalid_at(item, "Tasks can only declare `timeout` once"));
+                    }
+                    timeout = Some(compile_timeout(item, args)?);
+                }
+                Some(("finally", args)) => {
+                    check_sexprs("finally", args)?;
+                    for arg in args {
+                        check_control_flow(arg)?;
+                        finally.push(arg);
+                    }
+                }
+                _ => {
+                    check_control_flow(item)?;
+                    body.push(item);
+                }
+            }
         }
         Ok(Task {
             name,
             description,
-            items: rest.iter().collect(),
+            params,
+            needs,
+            env,
+            sources,
+            outputs,
+            fingerprint,
+            timeout,
+            items: body,
+            finally,
+            sexpr,
         })
     }
 }
 
-pub fn compile<'a>(prog: &'a ast::RhizValue) -> CompilationResult<HashMap<String, Task<'a>>> {
+/// Check that every task's dependencies exist and that they don't form a
+/// cycle.
+fn check_needs(tasks: &HashMap<String, Task>) -> CompilationResult<()> {
+    for task in tasks.values() {
+        for dep in &task.needs {
+            if !tasks.contains_key(dep) {
+                let kind = ErrorKind::UnknownTask {
+                    task: task.name.to_owned(),
+                    needed: dep.to_owned(),
+                };
+                return Err(error_at(task.sexpr, kind));
+            }
+        }
+    }
+
+    fn visit<'t>(
+        name: &'t str,
+        tasks: &'t HashMap<String, Task>,
+        path: &mut Vec<&'t str>,
+        checked: &mut HashSet<&'t str>,
+    ) -> CompilationResult<()> {
+        if checked.contains(name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|n| *n == name) {
+            let mut cycle: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+            cycle.push(name.to_owned());
+            let kind = ErrorKind::DependencyCycle(cycle);
+            return Err(error_at(tasks[name].sexpr, kind));
+        }
+        path.push(name);
+        for dep in &tasks[name].needs {
+            visit(dep, tasks, path, checked)?;
+        }
+        path.pop();
+        checked.insert(name);
+        Ok(())
+    }
+
+    let mut names: Vec<&String> = tasks.keys().collect();
+    names.sort();
+    let mut checked = HashSet::new();
+    for name in names {
+        visit(name, tasks, &mut Vec::new(), &mut checked)?;
+    }
+    Ok(())
+}
+
+/// The tasks and variables declared in a Rhizfile.
+pub struct Program<'a> {
+    pub tasks: HashMap<String, Task<'a>>,
+    /// Values of the `define`d variables, with their own references substituted.
+    pub variables: HashMap<String, String>,
+}
+
+/// Add a `(define name value)` declaration's variable to `variables`.
+fn compile_define(
+    sexpr: &ast::RhizValue,
+    args: &[ast::RhizValue],
+    variables: &mut HashMap<String, String>,
+) -> CompilationResult<()> {
+    if args.len() != 2 {
+        return Err(invalid_at(
+            sexpr,
+            "Definitions should be of the form (define name value)",
+        ));
+    }
+    let name = match &args[0] {
+        ast::RhizValue::String(s, _) if variables::is_valid_name(s) => s,
+        _ => return Err(invalid_at(&args[0], "Invalid variable name")),
+    };
+    if variables.contains_key(name) {
+        let kind = ErrorKind::DuplicateVariable(name.to_owned());
+        return Err(error_at(&args[0], kind));
+    }
+    let value = match &args[1] {
+        ast::RhizValue::String(s, _) => variables::substitute(s, |n| {
+            variables
+                .get(n)
+                .cloned()
+                .or_else(|| variables::lookup_env(n))
+        })
+        .map_err(|msg| invalid_at(&args[1], &msg))?,
+        _ => return Err(invalid_at(&args[1], "Variable values should be strings")),
+    };
+    variables.insert(name.to_owned(), value);
+    Ok(())
+}
+
+pub fn compile<'a>(prog: &'a ast::RhizValue) -> CompilationResult<Program<'a>> {
     match prog {
-        ast::RhizValue::Program(tasks) => {
-            let compiled_tasks = tasks.iter().map(Task::compile);
+        ast::RhizValue::Program(decls, _) => {
             let mut tasks: HashMap<String, Task<'a>> = HashMap::new();
-            for task in compiled_tasks {
-                match task {
-                    Ok(t) => tasks.insert(t.name.to_owned(), t),
-                    Err(e) => return Err(e),
-                };
+            let mut variables = HashMap::new();
+            for decl in decls {
+                if let Some(("define", args)) = split_form(decl) {
+                    compile_define(decl, args, &mut variables)?;
+                    continue;
+                }
+                let t = Task::compile(decl)?;
+                tasks.insert(t.name.to_owned(), t);
             }
-            Ok(tasks)
+            check_needs(&tasks)?;
+            Ok(Program { tasks, variables })
         }
-        _ => Err(CompilationError::from(
-            "I only know how to compile programs",
-        )),
+        _ => Err(invalid_at(prog, "I only know how to compile programs")),
+    }
+}
+
+#[test]
+fn test_compile_needs() {
+    let src = r#"(task "a" (log "a")) (task "b" (needs a) (log "b"))"#;
+    let prog = ast::parse_rhiz_program(src).unwrap();
+    let tasks = compile(&prog).unwrap().tasks;
+    assert_eq!(tasks["b"].needs, vec!["a".to_owned()]);
+    assert_eq!(tasks["b"].items.len(), 1);
+}
+
+#[test]
+fn test_compile_needs_errors() {
+    let err_cases = [
+        r#"(task "a" (needs b))"#,
+        r#"(task "a" (needs (b)))"#,
+        r#"(task "a" (needs a))"#,
+        r#"(task "a" (needs b)) (task "b" (needs c)) (task "c" (needs a))"#,
+    ];
+    for case in &err_cases {
+        let prog = ast::parse_rhiz_program(case).unwrap();
+        assert!(
+            compile(&prog).is_err(),
+            "Expected '{}' not to compile",
+            case
+        );
+    }
+    let prog = ast::parse_rhiz_program(err_cases[3]).unwrap();
+    match compile(&prog).err().unwrap().kind() {
+        ErrorKind::DependencyCycle(cycle) => assert_eq!(cycle, &["a", "b", "c", "a"]),
+        kind => panic!("Unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn test_compile_error_position() {
+    let prog = ast::parse_rhiz_source("(task\n  (a))", "Rhizfile").unwrap();
+    let err = compile(&prog).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Rhizfile:2:3: Task names should be strings"
+    );
+    let msg = format!("{:?}", err);
+    assert!(msg.contains("--> Rhizfile:2:3"), "{}", msg);
+}
+
+#[test]
+fn test_compile_defines() {
+    let src = r#"(define dist ./target/dist) (define app "${dist}/app") (task "a" (log "${app}"))"#;
+    let prog = ast::parse_rhiz_program(src).unwrap();
+    let variables = compile(&prog).unwrap().variables;
+    assert_eq!(variables["app"], "./target/dist/app");
+
+    let err_cases = [
+        "(define a)",
+        "(define a b c)",
+        "(define (a) b)",
+        "(define a (b))",
+        "(define a b) (define a c)",
+        "(define a ${b})",
+    ];
+    for case in &err_cases {
+        let prog = ast::parse_rhiz_program(case).unwrap();
+        assert!(
+            compile(&prog).is_err(),
+            "Expected '{}' not to compile",
+            case
+        );
+    }
+}
+
+#[test]
+fn test_compile_params() {
+    let src = r#"(task "deploy" (params target (profile "dev")) (log "${target}"))"#;
+    let prog = ast::parse_rhiz_program(src).unwrap();
+    let tasks = compile(&prog).unwrap().tasks;
+    let params: Vec<(&str, Option<&str>)> = tasks["deploy"]
+        .params
+        .iter()
+        .map(|p| (p.name.as_str(), p.default.as_deref()))
+        .collect();
+    assert_eq!(params, vec![("target", None), ("profile", Some("dev"))]);
+
+    let err_cases = [
+        r#"(task "a" (params (b)))"#,
+        r#"(task "a" (params (b c d)))"#,
+        r#"(task "a" (params b b))"#,
+        r#"(task "a" (params "b c"))"#,
+        r#"(task "a" (params b) (params c))"#,
+    ];
+    for case in &err_cases {
+        let prog = ast::parse_rhiz_program(case).unwrap();
+        assert!(
+            compile(&prog).is_err(),
+            "Expected '{}' not to compile",
+            case
+        );
+    }
+}
+
+#[test]
+fn test_compile_control_flow() {
+    let src = r#"
+        (task "a"
+          (if (os "linux") (log "linux") (log "other"))
+          (when (not (exists "target")) (log "clean") (log "really"))
+          (par (unless (env-set "CI") (log "local")))
+          (try (log "a") (catch (log "b")) (finally (log "c")))
+          (ignore-errors (log "d"))
+          (retry 3 500ms (log "e") (when (os "unix") (log "f")))
+          (retry ${attempts} (log "g"))
+          (in-dir "frontend" (exec npm test) (when (exists "a") (log "h")))
+          (timeout 90s)
+          (finally (log "e")))
+    "#;
+    let prog = ast::parse_rhiz_program(src).unwrap();
+    assert!(compile(&prog).is_ok());
+
+    let err_cases = [
+        r#"(task "a" (if (os "linux")))"#,
+        r#"(task "a" (if (os "linux") (log "a") (log "b") (log "c")))"#,
+        r#"(task "a" (when (frobnicated) (log "a")))"#,
+        r#"(task "a" (unless))"#,
+        r#"(task "a" (when (exists "a") "b"))"#,
+        r#"(task "a" (par (when (equal "a") (log "a"))))"#,
+        r#"(task "a" (when (exists "a") (if (not) (log "a"))))"#,
+        r#"(task "a" (try (log "a") (finally (log "b")) (catch (log "c"))))"#,
+        r#"(task "a" (try (catch (log "a")) (log "b")))"#,
+        r#"(task "a" (try (log "a") (catch "b")))"#,
+        r#"(task "a" (ignore-errors "b"))"#,
+        r#"(task "a" (when (exists "a") (finally (log "b"))))"#,
+        r#"(task "a" (finally (when (exists))))"#,
+        r#"(task "a" (retry))"#,
+        r#"(task "a" (retry 0 (log "a")))"#,
+        r#"(task "a" (retry 3 soon (log "a")))"#,
+        r#"(task "a" (retry 3 1s (log "a") "b"))"#,
+        r#"(task "a" (retry 3 (when (exists))))"#,
+        r#"(task "a" (in-dir))"#,
+        r#"(task "a" (in-dir (log "a")))"#,
+        r#"(task "a" (in-dir "a" "b"))"#,
+        r#"(task "a" (timeout 5 days))"#,
+        r#"(task "a" (timeout 5x))"#,
+        r#"(task "a" (timeout 5s) (timeout 6s))"#,
+    ];
+    for case in &err_cases {
+        let prog = ast::parse_rhiz_program(case).unwrap();
+        assert!(
+            compile(&prog).is_err(),
+            "Expected '{}' not to compile",
+            case
+        );
     }
 }
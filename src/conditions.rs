@@ -0,0 +1,135 @@
+//! Conditions for the `if`, `when` and `unless` forms, e.g.
+//!
+//! ```ignore
+//! (when (and (env-set "CI") (not (os "windows")))
+//!   (exec ./ci-setup.sh))
+//! ```
+use std::path::Path;
+
+use crate::ast::RhizValue;
+use crate::compiler::split_form;
+use crate::executor::{Context, ExecutionError};
+
+/// The conditions, and how many arguments each takes (`None` for any number
+/// above the minimum). The arguments of `not`, `and` and `or` are conditions;
+/// the others' are strings.
+const CONDITIONS: &[(&str, usize, Option<usize>)] = &[
+    ("exists", 1, Some(1)),
+    ("env-set", 1, Some(1)),
+    ("os", 1, Some(1)),
+    ("equal", 2, Some(2)),
+    ("not", 1, Some(1)),
+    ("and", 1, None),
+    ("or", 1, None),
+];
+
+fn takes_conditions(name: &str) -> bool {
+    matches!(name, "not" | "and" | "or")
+}
+
+/// Check that a condition is well-formed, giving the message and the value
+/// it's about if it isn't.
+pub fn check(condition: &RhizValue) -> Result<(), (String, &RhizValue)> {
+    let (name, args) = match split_form(condition) {
+        Some(form) => form,
+        None => {
+            return Err((
+                "Expected a condition, e.g. (exists \"path\")".to_owned(),
+                condition,
+            ))
+        }
+    };
+    let (min, max) = match CONDITIONS.iter().find(|(n, _, _)| *n == name) {
+        Some((_, min, max)) => (*min, *max),
+        None => return Err((format!("Unknown condition: '{}'", name), condition)),
+    };
+    if args.len() < min || max.is_some_and(|max| args.len() > max) {
+        let msg = match max {
+            Some(max) if max == min => format!("`{}` takes {} argument(s)", name, min),
+            _ => format!("`{}` takes at least {} argument(s)", name, min),
+        };
+        return Err((msg, condition));
+    }
+    for arg in args {
+        if takes_conditions(name) {
+            check(arg)?;
+        } else if !matches!(arg, RhizValue::String(_, _)) {
+            return Err((format!("`{}` takes strings", name), arg));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a condition holds. Its strings have their variable references
+/// substituted, and paths are relative to the context's working directory.
+pub fn evaluate(condition: &RhizValue, ctx: &Context) -> Result<bool, ExecutionError> {
+    check(condition)
+        .map_err(|(msg, item)| ExecutionError::invalid(msg).located(item.position()))?;
+    evaluate_checked(condition, ctx)
+}
+
+fn evaluate_checked(condition: &RhizValue, ctx: &Context) -> Result<bool, ExecutionError> {
+    let (name, args) = split_form(condition).expect("conditions should be checked");
+    let result = match name {
+        "exists" => ctx
+            .working_dir
+            .join(Path::new(&ctx.expand(&args[0])?))
+            .exists(),
+        "env-set" => ctx.env_var(&ctx.expand(&args[0])?).is_some(),
+        "os" => {
+            let os = ctx.expand(&args[0])?;
+            os == std::env::consts::OS || os == std::env::consts::FAMILY
+        }
+        "equal" => ctx.expand(&args[0])? == ctx.expand(&args[1])?,
+        "not" => !evaluate_checked(&args[0], ctx)?,
+        "and" => {
+            for arg in args {
+                if !evaluate_checked(arg, ctx)? {
+                    return Ok(false);
+                }
+            }
+            true
+        }
+        "or" => {
+            for arg in args {
+                if evaluate_checked(arg, ctx)? {
+                    return Ok(true);
+                }
+            }
+            false
+        }
+        _ => unreachable!("unknown condition: {}", name),
+    };
+    Ok(result)
+}
+
+#[test]
+fn test_check() {
+    use crate::ast::parse_rhiz_program;
+
+    let valid = [
+        r#"(exists "target")"#,
+        r#"(equal ${mode} release)"#,
+        r#"(not (and (env-set CI) (or (os linux) (os macos))))"#,
+    ];
+    let invalid = [
+        r#"((exists "target"))"#,
+        r#"(exist "target")"#,
+        r#"(equal a)"#,
+        r#"(os (linux))"#,
+        r#"(not (exists))"#,
+        r#"(and)"#,
+    ];
+    for (case, ok) in valid
+        .iter()
+        .map(|c| (c, true))
+        .chain(invalid.iter().map(|c| (c, false)))
+    {
+        let program = parse_rhiz_program(case).unwrap();
+        let condition = match &program {
+            RhizValue::Program(items, _) => &items[0],
+            _ => unreachable!(),
+        };
+        assert_eq!(check(condition).is_ok(), ok, "{}", case);
+    }
+}
@@ -1,99 +1,1001 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::ast;
+use crate::cache::{Cache, Fingerprint, CACHE_FILE_NAME};
 use crate::compiler;
+use crate::files;
 use crate::functions;
+use crate::jobs::JobSlots;
+use crate::output::{Output, Stream};
+use crate::timeout::{self, Deadline};
+use crate::variables;
 
 pub type ExecutionResult = Result<(), ExecutionError>;
 
+/// The ways executing a task can fail.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// There's no task with this name.
+    UnknownTask(String),
+    /// A task was invoked with arguments which don't fit its parameters.
+    TaskArguments { task: String, message: String },
+    /// A task which was needed failed (its own error is reported separately).
+    TaskFailed(String),
+    /// There's no function with this name.
+    UnknownFunction(String),
+    /// A function was called with too few or too many arguments; `max` is
+    /// `None` if it takes any number above `min`.
+    Arity {
+        function: String,
+        min: usize,
+        max: Option<usize>,
+    },
+    /// A function's argument isn't of the type it should be.
+    ArgumentType {
+        function: String,
+        index: usize,
+        expected: &'static str,
+    },
+    /// A value which isn't valid where it's used, e.g. a reference to an
+    /// undefined variable or an unknown option.
+    Invalid(String),
+    /// A file or directory isn't something the function can act on.
+    BadPath { path: PathBuf, message: String },
+    /// An external command couldn't be started.
+    Spawn { command: String, source: io::Error },
+    /// An external command exited unsuccessfully, with an exit code or
+    /// (on Unix) because of a signal.
+    CommandFailed {
+        command: String,
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+    /// An I/O operation failed, on `path` if it's known.
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// A command or task ran for longer than its time limit; `what` says
+    /// which, e.g. "Task 'test'".
+    TimedOut { what: String, limit: Duration },
+    /// Several things failed, e.g. a command and the cleanup after it, in the
+    /// order they failed.
+    Multiple(Vec<ExecutionError>),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnknownTask(task) => write!(f, "No such task: '{}'", task),
+            ErrorKind::TaskArguments { message, .. } => write!(f, "{}", message),
+            ErrorKind::TaskFailed(task) => write!(f, "Task '{}' failed", task),
+            ErrorKind::UnknownFunction(name) => write!(f, "Unknown function: {}", name),
+            ErrorKind::Arity { function, min, max } => match max {
+                Some(max) if max == min => {
+                    write!(f, "`{}` takes {} argument(s)", function, min)
+                }
+                Some(max) => write!(f, "`{}` takes {} to {} arguments", function, min, max),
+                None => write!(f, "`{}` takes at least {} argument(s)", function, min),
+            },
+            ErrorKind::ArgumentType {
+                function,
+                index,
+                expected,
+            } => write!(
+                f,
+                "Expected argument {} to `{}` to be {}",
+                index, function, expected
+            ),
+            ErrorKind::Invalid(msg) => write!(f, "{}", msg),
+            ErrorKind::BadPath { path, message } => write!(f, "{} ({})", message, path.display()),
+            ErrorKind::Spawn { command, source } => {
+                write!(f, "Couldn't run '{}': {}", command, source)
+            }
+            ErrorKind::CommandFailed {
+                command,
+                code,
+                signal,
+            } => {
+                write!(f, "External command returned an error code: {}", command)?;
+                match (code, signal) {
+                    (Some(c), _) => write!(f, " (exit code: {})", c),
+                    (None, Some(s)) => write!(f, " (killed by signal {})", s),
+                    (None, None) => Ok(()),
+                }
+            }
+            ErrorKind::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {}", path.display(), source),
+            ErrorKind::Io { path: None, source } => write!(f, "{}", source),
+            ErrorKind::TimedOut { what, limit } => {
+                write!(f, "{} timed out after {:?}", what, limit)
+            }
+            ErrorKind::Multiple(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{} errors: {}", errors.len(), errors.join("; "))
+            }
+        }
+    }
+}
+
+/// An error from executing a task, and where in the Rhizfile it happened (if
+/// that's known).
 pub struct ExecutionError {
-    msg: String,
+    kind: ErrorKind,
+    position: Option<ast::Position>,
+}
+
+impl ExecutionError {
+    pub fn new(kind: ErrorKind) -> Self {
+        ExecutionError {
+            kind,
+            position: None,
+        }
+    }
+
+    /// An I/O error from an operation on `path`.
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        ExecutionError::new(ErrorKind::Io {
+            path: Some(path.to_path_buf()),
+            source,
+        })
+    }
+
+    /// An error for a value which isn't valid where it's used.
+    pub fn invalid<S: Into<String>>(msg: S) -> Self {
+        ExecutionError::new(ErrorKind::Invalid(msg.into()))
+    }
+
+    /// Combine the errors of several failures into one, flattening any which
+    /// are themselves combined.
+    pub(crate) fn multiple(errors: Vec<ExecutionError>) -> Self {
+        let mut flattened = Vec::new();
+        for error in errors {
+            match error.kind {
+                ErrorKind::Multiple(inner) => flattened.extend(inner),
+                _ => flattened.push(error),
+            }
+        }
+        if flattened.len() == 1 {
+            return flattened.pop().unwrap();
+        }
+        ExecutionError::new(ErrorKind::Multiple(flattened))
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Where the error happened in the Rhizfile.
+    pub fn position(&self) -> Option<&ast::Position> {
+        self.position.as_ref()
+    }
+
+    /// Record where the error happened, unless a more specific position is
+    /// already known.
+    pub fn located(mut self, position: &ast::Position) -> Self {
+        if self.position.is_none() {
+            self.position = Some(position.clone());
+        }
+        self
+    }
+}
+
+/// Attach the path an I/O operation was on to its error.
+pub(crate) fn at_path<T>(result: io::Result<T>, path: &Path) -> Result<T, ExecutionError> {
+    result.map_err(|e| ExecutionError::io(path, e))
 }
 
-impl std::fmt::Debug for ExecutionError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.msg)
+/// Gather the errors of several things which were all attempted. A task's
+/// `TaskFailed` errors are left out when the error it failed with is there.
+pub(crate) fn collect_errors<I>(results: I) -> ExecutionResult
+where
+    I: IntoIterator<Item = ExecutionResult>,
+{
+    let mut errors: Vec<ExecutionError> = results.into_iter().filter_map(Result::err).collect();
+    if errors
+        .iter()
+        .any(|e| !matches!(e.kind, ErrorKind::TaskFailed(_)))
+    {
+        errors.retain(|e| !matches!(e.kind, ErrorKind::TaskFailed(_)));
+    }
+    match errors.len() {
+        0 => Ok(()),
+        _ => Err(ExecutionError::multiple(errors)),
     }
 }
 
-impl From<String> for ExecutionError {
-    fn from(msg: String) -> Self {
-        ExecutionError { msg }
+/// The result of doing one thing and then another (e.g. cleaning up after
+/// it), with both errors if both fail.
+pub(crate) fn combine_results(first: ExecutionResult, second: ExecutionResult) -> ExecutionResult {
+    match (first, second) {
+        (Ok(()), result) | (result, Ok(())) => result,
+        (Err(a), Err(b)) => Err(ExecutionError::multiple(vec![a, b])),
     }
 }
 
-impl From<&str> for ExecutionError {
-    fn from(msg: &str) -> Self {
-        ExecutionError {
-            msg: msg.to_owned(),
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.position, &self.kind) {
+            (Some(p), kind) if !matches!(kind, ErrorKind::Multiple(_)) => {
+                write!(f, "{}: {}", p, kind)
+            }
+            (_, kind) => write!(f, "{}", kind),
         }
     }
 }
 
-impl From<Box<dyn std::error::Error>> for ExecutionError {
-    fn from(error: Box<dyn std::error::Error>) -> Self {
-        ExecutionError {
-            msg: error.to_string(),
+/// Shows the error's message under the Rhizfile snippet it's about (or each
+/// error's, for several).
+impl fmt::Debug for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.position, &self.kind) {
+            (_, ErrorKind::Multiple(errors)) => {
+                let errors: Vec<String> = errors.iter().map(|e| format!("{:?}", e)).collect();
+                write!(f, "{}", errors.join("\n\n"))
+            }
+            (Some(p), kind) => write!(f, "{}", p.annotate(&kind.to_string())),
+            (None, kind) => write!(f, "{}", kind),
         }
     }
 }
 
-impl From<std::io::Error> for ExecutionError {
-    fn from(error: std::io::Error) -> Self {
-        ExecutionError {
-            msg: error.to_string(),
+impl std::error::Error for ExecutionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Spawn { source, .. } | ErrorKind::Io { source, .. } => Some(source),
+            ErrorKind::Multiple(errors) => errors.first().map(|e| e as _),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for ExecutionError {
+    fn from(kind: ErrorKind) -> Self {
+        ExecutionError::new(kind)
+    }
+}
+
+impl From<io::Error> for ExecutionError {
+    fn from(source: io::Error) -> Self {
+        ExecutionError::new(ErrorKind::Io { path: None, source })
+    }
+}
+
+/// The shell which runs `sh` scripts, unless another is configured.
+#[cfg(unix)]
+pub const DEFAULT_SHELL: &str = "/bin/sh -c";
+#[cfg(not(unix))]
+pub const DEFAULT_SHELL: &str = "cmd /C";
+
+/// The state a task's functions are executed in.
+#[derive(Clone)]
+pub struct Context {
+    /// The directory which relative paths are resolved against.
+    pub working_dir: PathBuf,
+    /// The Rhizfile's directory, which destructive functions stay inside.
+    pub root: PathBuf,
+    variables: Arc<HashMap<String, String>>,
+    /// Variables set while a task runs (by `exec :capture`), which are shared
+    /// by the contexts made from the task's.
+    set_variables: Arc<Mutex<HashMap<String, String>>>,
+    /// Changes to external commands' environment; `None` unsets a variable.
+    pub env: Vec<(String, Option<String>)>,
+    /// Describe actions instead of performing them.
+    pub dry_run: bool,
+    /// Slots for the external commands which can run at once.
+    pub(crate) job_slots: Arc<JobSlots>,
+    /// How the output of parallel branches started in this context is shown.
+    pub output_mode: OutputMode,
+    pub(crate) output: Arc<Output>,
+    /// When the innermost command or task with a time limit has to finish.
+    pub(crate) deadline: Option<Deadline>,
+    /// The program (and its options) which runs `sh` scripts, which are
+    /// passed to it as its last argument.
+    pub shell: String,
+}
+
+impl Context {
+    pub fn new(working_dir: &Path, variables: HashMap<String, String>) -> Context {
+        Context {
+            working_dir: working_dir.to_path_buf(),
+            root: working_dir.to_path_buf(),
+            variables: Arc::new(variables),
+            set_variables: Arc::default(),
+            env: Vec::new(),
+            dry_run: false,
+            job_slots: JobSlots::per_cpu(),
+            output_mode: OutputMode::default(),
+            output: Arc::new(Output::Terminal),
+            deadline: None,
+            shell: DEFAULT_SHELL.to_owned(),
+        }
+    }
+
+    /// Print a line of output, labelled or buffered if this context is a
+    /// parallel branch's.
+    pub fn print(&self, line: &str) {
+        self.output.print(Stream::Stdout, line);
+    }
+
+    /// Print a line to stderr, like `print`.
+    pub fn print_error(&self, line: &str) {
+        self.output.print(Stream::Stderr, line);
+    }
+
+    /// Make the context for a parallel branch, whose output is shown
+    /// according to the output mode. The branch's output has to be
+    /// `finish`ed once it's done.
+    pub(crate) fn branch(&self, label: &str) -> Context {
+        let output = match self.output_mode {
+            OutputMode::Interleaved => return self.clone(),
+            OutputMode::Prefixed => Output::prefixed(label, &self.output),
+            OutputMode::Block => Output::block(label, &self.output),
+        };
+        Context {
+            output,
+            ..self.clone()
+        }
+    }
+
+    /// Pass on a branch's buffered output (see `branch`).
+    pub(crate) fn finish_branch(&self) {
+        self.output.finish();
+    }
+
+    /// Make a context in which at most `limit` external commands run at once
+    /// (as well as being within this context's limit).
+    pub fn with_job_limit(&self, limit: usize) -> Context {
+        Context {
+            job_slots: JobSlots::nested(limit, &self.job_slots),
+            ..self.clone()
+        }
+    }
+
+    /// Make a context which has to finish within `limit`, unless it already
+    /// has to finish sooner. `what` names what's limited in the error.
+    pub(crate) fn with_timeout(&self, limit: Duration, what: String) -> Context {
+        let deadline = Deadline::new(limit, what);
+        match &self.deadline {
+            Some(current) if current.at <= deadline.at => self.clone(),
+            _ => Context {
+                deadline: Some(deadline),
+                ..self.clone()
+            },
+        }
+    }
+
+    /// Fail if this context's time limit has passed.
+    pub(crate) fn check_deadline(&self) -> ExecutionResult {
+        match &self.deadline {
+            Some(deadline) if Instant::now() >= deadline.at => {
+                Err(ExecutionError::new(ErrorKind::TimedOut {
+                    what: deadline.what.clone(),
+                    limit: deadline.limit,
+                }))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Get a string argument's value, with variable references substituted.
+    pub fn expand(&self, value: &ast::RhizValue) -> Result<String, ExecutionError> {
+        match value {
+            ast::RhizValue::String(s, _) => self.expand_str(s, value.position()),
+            _ => Err(ExecutionError::invalid("Expected a string").located(value.position())),
+        }
+    }
+
+    fn expand_str(&self, text: &str, position: &ast::Position) -> Result<String, ExecutionError> {
+        variables::substitute(text, |name| {
+            match name.strip_prefix(variables::ENV_PREFIX) {
+                Some(env_name) => self.env_var(env_name),
+                None => self
+                    .set_variables
+                    .lock()
+                    .unwrap()
+                    .get(name)
+                    .or_else(|| self.variables.get(name))
+                    .cloned(),
+            }
+        })
+        .map_err(|msg| ExecutionError::invalid(msg).located(position))
+    }
+
+    /// The value an environment variable will have for external commands.
+    pub(crate) fn env_var(&self, name: &str) -> Option<String> {
+        match self.env.iter().rev().find(|(n, _)| n == name) {
+            Some((_, value)) => value.clone(),
+            None => std::env::var(name).ok(),
+        }
+    }
+
+    /// Set a variable for the rest of the task (including in contexts made
+    /// from this one).
+    pub(crate) fn set_variable(&self, name: &str, value: &str) {
+        let mut set_variables = self.set_variables.lock().unwrap();
+        set_variables.insert(name.to_owned(), value.to_owned());
+    }
+
+    /// Make a context for a task, with its own set of variables set while it
+    /// runs.
+    fn for_task(&self, variables: HashMap<String, String>) -> Context {
+        Context {
+            set_variables: Arc::default(),
+            ..self.with_variables(variables)
+        }
+    }
+
+    /// Make a context in which relative paths are resolved against `dir`.
+    pub fn in_dir(&self, dir: PathBuf) -> Context {
+        Context {
+            working_dir: dir,
+            ..self.clone()
+        }
+    }
+
+    /// Make a context with some extra variables, which take precedence over
+    /// the existing ones.
+    pub fn with_variables(&self, extra: HashMap<String, String>) -> Context {
+        let mut variables = (*self.variables).clone();
+        variables.extend(extra);
+        Context {
+            variables: Arc::new(variables),
+            ..self.clone()
+        }
+    }
+
+    /// Make a context with some environment settings (`NAME=value` or
+    /// `:unset NAME`) applied.
+    pub fn with_env(&self, settings: &[&ast::RhizValue]) -> Result<Context, ExecutionError> {
+        let settings = variables::parse_env_settings(settings)
+            .map_err(|(msg, item)| ExecutionError::invalid(msg).located(item.position()))?;
+        let mut ctx = self.clone();
+        for setting in settings {
+            let value = match setting.value {
+                Some(v) => Some(self.expand_str(v, setting.source.position())?),
+                None => None,
+            };
+            ctx.env.push((setting.name.to_owned(), value));
         }
+        Ok(ctx)
     }
 }
 
 pub fn execute(
     func_name: &ast::RhizValue,
     args: &[ast::RhizValue],
-    working_dir: &Path,
+    ctx: &Context,
 ) -> ExecutionResult {
     let func = match functions::look_up_function(func_name) {
         Some(f) => f,
         None => {
-            let msg = format!("Invalid function: {:?}", func_name);
-            return Err(ExecutionError::from(msg));
+            let name = match func_name {
+                ast::RhizValue::String(s, _) => s.to_owned(),
+                _ => String::from(func_name),
+            };
+            let kind = ErrorKind::UnknownFunction(name);
+            return Err(ExecutionError::new(kind).located(func_name.position()));
         }
     };
-    func(args, working_dir)
+    func.call(args, ctx)
 }
 
-pub fn exec_sexpr(contents: &[ast::RhizValue], working_dir: &Path) -> ExecutionResult {
+pub fn exec_sexpr(sexpr: &ast::RhizValue, ctx: &Context) -> ExecutionResult {
+    let contents = match sexpr {
+        ast::RhizValue::SExpr(contents, _) => contents,
+        _ => {
+            let msg = "Can only eval an s-expression";
+            return Err(ExecutionError::invalid(msg).located(sexpr.position()));
+        }
+    };
     if contents.is_empty() {
         let msg = "Can't eval an empty expression";
-        return Err(ExecutionError::from(msg));
+        return Err(ExecutionError::invalid(msg).located(sexpr.position()));
     }
+    ctx.check_deadline()
+        .map_err(|e| e.located(sexpr.position()))?;
     let name = &contents[0];
     let args = &contents[1..contents.len()];
-    execute(name, args, working_dir)
+    execute(name, args, ctx).map_err(|e| e.located(sexpr.position()))
 }
 
-pub fn exec_task<S>(
-    task_name: &str,
-    tasks: &HashMap<String, compiler::Task, S>,
-    working_dir: &Path,
-) -> ExecutionResult
+/// Execute some expressions in order, stopping at the first that fails.
+pub fn exec_sexprs<'v, I>(sexprs: I, ctx: &Context) -> ExecutionResult
 where
-    S: ::std::hash::BuildHasher,
+    I: IntoIterator<Item = &'v ast::RhizValue>,
 {
-    let task = match tasks.get(task_name) {
-        Some(t) => t,
-        None => {
-            return Err(ExecutionError::from(format!(
-                "No such task: '{}'",
-                task_name
-            )));
+    for sexpr in sexprs {
+        exec_sexpr(sexpr, ctx)?;
+    }
+    Ok(())
+}
+
+/// Give a task's parameters values from the arguments it was invoked with.
+/// Arguments are either `name=value` or positional values, which are given to
+/// the parameters that don't have a named argument, in order.
+pub fn bind_params(
+    task: &compiler::Task,
+    args: &[String],
+) -> Result<HashMap<String, String>, ExecutionError> {
+    let mut bound = HashMap::new();
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.find('=') {
+            Some(i) => {
+                let name = &arg[..i];
+                if !task.params.iter().any(|p| p.name == name) {
+                    let msg = format!("Task '{}' has no parameter '{}'", task.name, name);
+                    return Err(task_arguments_error(task, msg));
+                }
+                bound.insert(name.to_owned(), arg[i + 1..].to_owned());
+            }
+            None => positional.push(arg),
         }
-    };
-    for item in &task.items {
-        match item {
-            ast::RhizValue::SExpr(contents) => exec_sexpr(contents, working_dir)?,
-            _ => unreachable!(),
+    }
+    let mut positional = positional.into_iter();
+    for param in &task.params {
+        if bound.contains_key(&param.name) {
+            continue;
+        }
+        let value = match (positional.next(), &param.default) {
+            (Some(v), _) => v.to_owned(),
+            (None, Some(d)) => d.to_owned(),
+            (None, None) => {
+                let msg = format!(
+                    "Task '{}' needs a value for its '{}' parameter",
+                    task.name, param.name
+                );
+                return Err(task_arguments_error(task, msg));
+            }
         };
+        bound.insert(param.name.to_owned(), value);
+    }
+    if let Some(extra) = positional.next() {
+        let msg = format!("Too many arguments for task '{}' ('{}')", task.name, extra);
+        return Err(task_arguments_error(task, msg));
+    }
+    Ok(bound)
+}
+
+fn task_arguments_error(task: &compiler::Task, message: String) -> ExecutionError {
+    ExecutionError::new(ErrorKind::TaskArguments {
+        task: task.name.to_owned(),
+        message,
+    })
+}
+
+fn look_up_task<'t, 'a>(
+    task_name: &str,
+    tasks: &'t HashMap<String, compiler::Task<'a>>,
+) -> Result<&'t compiler::Task<'a>, ExecutionError> {
+    tasks
+        .get(task_name)
+        .ok_or_else(|| ExecutionError::new(ErrorKind::UnknownTask(task_name.to_owned())))
+}
+
+/// Bind the parameters of the tasks a task (transitively) needs, which only
+/// get their default values.
+fn bind_needed_params(
+    task: &compiler::Task,
+    tasks: &HashMap<String, compiler::Task>,
+    bindings: &mut HashMap<String, HashMap<String, String>>,
+) -> ExecutionResult {
+    for dep in &task.needs {
+        if bindings.contains_key(dep) {
+            continue;
+        }
+        let dep_task = look_up_task(dep, tasks)?;
+        bindings.insert(dep.to_owned(), bind_params(dep_task, &[])?);
+        bind_needed_params(dep_task, tasks, bindings)?;
     }
     Ok(())
 }
+
+/// Find the files matching some of a task's patterns. If `require_match` is
+/// set, `None` is returned when a pattern doesn't match anything.
+fn expand_patterns(
+    patterns: &[&ast::RhizValue],
+    ctx: &Context,
+    require_match: bool,
+) -> Result<Option<Vec<PathBuf>>, ExecutionError> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let matches = files::expand_pattern(&ctx.working_dir, &ctx.expand(pattern)?)
+            .map_err(|msg| ExecutionError::invalid(msg).located(pattern.position()))?;
+        if require_match && matches.is_empty() {
+            return Ok(None);
+        }
+        paths.extend(matches);
+    }
+    Ok(Some(paths))
+}
+
+/// Whether a task's declared outputs are all newer than its sources.
+fn is_up_to_date(task: &compiler::Task, ctx: &Context) -> Result<bool, ExecutionError> {
+    if task.outputs.is_empty() {
+        return Ok(false);
+    }
+    let outputs = match expand_patterns(&task.outputs, ctx, true)? {
+        Some(paths) => paths,
+        None => return Ok(false),
+    };
+    let sources = expand_patterns(&task.sources, ctx, false)?.unwrap_or_default();
+    Ok(files::is_up_to_date(&sources, &outputs)?)
+}
+
+/// Render a value, with its variable references substituted where possible.
+fn expanded_text(value: &ast::RhizValue, ctx: &Context) -> String {
+    match value {
+        ast::RhizValue::String(s, _) => {
+            format!("{:?}", ctx.expand(value).unwrap_or_else(|_| s.clone()))
+        }
+        ast::RhizValue::SExpr(items, _) | ast::RhizValue::Program(items, _) => {
+            let items: Vec<String> = items.iter().map(|i| expanded_text(i, ctx)).collect();
+            format!("({})", items.join(" "))
+        }
+    }
+}
+
+/// Fingerprint a task's inputs: its body (with variables substituted), its
+/// environment settings, and the names and contents of its sources.
+fn fingerprint_task(task: &compiler::Task, ctx: &Context) -> Result<String, ExecutionError> {
+    let mut fingerprint = Fingerprint::new();
+    fingerprint.update_field(task.name.as_bytes());
+    for item in task.items.iter().chain(&task.finally) {
+        fingerprint.update_field(expanded_text(item, ctx).as_bytes());
+    }
+    for (name, value) in &ctx.env {
+        fingerprint.update_field(format!("{}={:?}", name, value).as_bytes());
+    }
+    let sources = expand_patterns(&task.sources, ctx, false)?.unwrap_or_default();
+    for path in sources {
+        let name = path.strip_prefix(&ctx.working_dir).unwrap_or(&path);
+        fingerprint.update_field(name.to_string_lossy().as_bytes());
+        if path.is_file() {
+            at_path(fingerprint.update_file(&path), &path)?;
+        }
+    }
+    Ok(fingerprint.finish())
+}
+
+/// Bind the parameters of the invoked tasks and the tasks they need.
+fn bind_invocations(
+    invocations: &[Invocation],
+    tasks: &HashMap<String, compiler::Task>,
+) -> Result<HashMap<String, HashMap<String, String>>, ExecutionError> {
+    let mut bindings = HashMap::new();
+    for invocation in invocations {
+        if bindings.contains_key(&invocation.task) {
+            let kind = ErrorKind::TaskArguments {
+                task: invocation.task.to_owned(),
+                message: format!("Task '{}' is invoked more than once", invocation.task),
+            };
+            return Err(ExecutionError::new(kind));
+        }
+        let task = look_up_task(&invocation.task, tasks)?;
+        bindings.insert(
+            invocation.task.to_owned(),
+            bind_params(task, &invocation.args)?,
+        );
+    }
+    for invocation in invocations {
+        let task = look_up_task(&invocation.task, tasks)?;
+        bind_needed_params(task, tasks, &mut bindings)?;
+    }
+    Ok(bindings)
+}
+
+/// The `sources` patterns (with variables substituted) of the invoked tasks
+/// and the tasks they need.
+pub fn source_patterns(
+    invocations: &[Invocation],
+    program: &compiler::Program,
+    working_dir: &Path,
+) -> Result<Vec<String>, ExecutionError> {
+    let bindings = bind_invocations(invocations, &program.tasks)?;
+    let ctx = Context::new(working_dir, program.variables.clone());
+    let mut patterns = Vec::new();
+    for (task_name, task_bindings) in bindings {
+        let task = look_up_task(&task_name, &program.tasks)?;
+        let task_ctx = ctx.with_variables(task_bindings).with_env(&task.env)?;
+        for pattern in &task.sources {
+            patterns.push(task_ctx.expand(pattern)?);
+        }
+    }
+    patterns.sort();
+    patterns.dedup();
+    Ok(patterns)
+}
+
+/// A task to run, and the arguments for its parameters (see `bind_params`).
+pub struct Invocation {
+    pub task: String,
+    pub args: Vec<String>,
+}
+
+/// How the output of parallel branches (`par`'s, and tasks run with
+/// `parallel`) is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputMode {
+    /// As it's written, so lines from different branches can interleave.
+    #[default]
+    Interleaved,
+    /// Line by line, each prefixed with the branch's label.
+    Prefixed,
+    /// All of a branch's output together, once the branch has finished.
+    Block,
+}
+
+impl std::str::FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interleaved" => Ok(OutputMode::Interleaved),
+            "prefix" => Ok(OutputMode::Prefixed),
+            "block" => Ok(OutputMode::Block),
+            _ => Err(format!(
+                "Unknown output mode '{}' (expected interleaved, prefix or block)",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings for a run of one or more tasks.
+#[derive(Default)]
+pub struct Options {
+    /// Run the invoked tasks concurrently rather than one after the other.
+    pub parallel: bool,
+    /// Print the actions that would be performed, rather than performing them.
+    pub dry_run: bool,
+    /// After a task fails, carry on with the tasks which don't need it. This
+    /// doesn't reach inside a task: its remaining items are skipped (apart
+    /// from `finally` ones), since they usually rely on the failed one.
+    pub keep_going: bool,
+    /// How many external commands can run at once (by default, one per CPU).
+    pub jobs: Option<usize>,
+    /// How the output of parallel branches is shown.
+    pub output: OutputMode,
+    /// The shell for `sh` scripts, instead of `DEFAULT_SHELL`.
+    pub shell: Option<String>,
+}
+
+enum TaskState {
+    Running,
+    Finished,
+    Failed,
+}
+
+/// The tasks executed (or being executed) in a run, so that each one is run
+/// at most once even when several tasks need it.
+struct Run<'p, 'a> {
+    tasks: &'p HashMap<String, compiler::Task<'a>>,
+    bindings: HashMap<String, HashMap<String, String>>,
+    states: Mutex<HashMap<String, TaskState>>,
+    state_changed: Condvar,
+    /// The Rhizfile's directory.
+    root: PathBuf,
+    /// The fingerprint cache, loaded when it's first needed.
+    cache: Mutex<Option<Cache>>,
+    keep_going: bool,
+}
+
+/// Execute a task's items (but not its `finally` ones) within its time limit.
+fn exec_task_items(task: &compiler::Task, task_ctx: &Context) -> ExecutionResult {
+    match task.timeout {
+        Some(value) => {
+            let limit = timeout::parse_duration(&task_ctx.expand(value)?)
+                .map_err(|msg| ExecutionError::invalid(msg).located(value.position()))?;
+            let what = format!("Task '{}'", task.name);
+            exec_sexprs(
+                task.items.iter().copied(),
+                &task_ctx.with_timeout(limit, what),
+            )
+        }
+        None => exec_sexprs(task.items.iter().copied(), task_ctx),
+    }
+}
+
+impl<'p, 'a> Run<'p, 'a> {
+    /// Execute a task, after executing the tasks it needs, unless it's already
+    /// been executed.
+    fn exec_task_once(&self, task_name: &str, ctx: &Context) -> ExecutionResult {
+        {
+            let mut states = self.states.lock().unwrap();
+            loop {
+                match states.get(task_name) {
+                    Some(TaskState::Finished) => return Ok(()),
+                    Some(TaskState::Failed) => {
+                        let kind = ErrorKind::TaskFailed(task_name.to_owned());
+                        return Err(ExecutionError::new(kind));
+                    }
+                    Some(TaskState::Running) => {
+                        states = self.state_changed.wait(states).unwrap();
+                    }
+                    None => break,
+                }
+            }
+            states.insert(task_name.to_owned(), TaskState::Running);
+        }
+        let result = self.exec_task(task_name, ctx);
+        let state = match result {
+            Ok(_) => TaskState::Finished,
+            Err(_) => TaskState::Failed,
+        };
+        self.states
+            .lock()
+            .unwrap()
+            .insert(task_name.to_owned(), state);
+        self.state_changed.notify_all();
+        result
+    }
+
+    fn exec_task(&self, task_name: &str, ctx: &Context) -> ExecutionResult {
+        let task = look_up_task(task_name, self.tasks)?;
+        if self.keep_going {
+            let results: Vec<ExecutionResult> = task
+                .needs
+                .iter()
+                .map(|dep| self.exec_task_once(dep, ctx))
+                .collect();
+            collect_errors(results)?;
+        } else {
+            for dep in &task.needs {
+                self.exec_task_once(dep, ctx)?;
+            }
+        }
+        let task_ctx = ctx
+            .for_task(self.bindings[task_name].clone())
+            .with_env(&task.env)?;
+        let fingerprint = if task.fingerprint {
+            Some(fingerprint_task(task, &task_ctx)?)
+        } else {
+            None
+        };
+        let up_to_date = match &fingerprint {
+            Some(f) => {
+                self.cached_fingerprint(task_name)?.as_ref() == Some(f)
+                    && expand_patterns(&task.outputs, &task_ctx, true)?.is_some()
+            }
+            None => is_up_to_date(task, &task_ctx)?,
+        };
+        if up_to_date {
+            task_ctx.print(&format!("Task '{}' is up to date", task_name));
+            return Ok(());
+        }
+        let result = exec_task_items(task, &task_ctx);
+        combine_results(result, exec_sexprs(task.finally.iter().copied(), &task_ctx))?;
+        if let (Some(f), false) = (fingerprint, ctx.dry_run) {
+            self.store_fingerprint(task_name, f)?;
+        }
+        Ok(())
+    }
+
+    fn with_cache<T, F>(&self, f: F) -> Result<T, ExecutionError>
+    where
+        F: FnOnce(&mut Cache) -> Result<T, ExecutionError>,
+    {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.is_none() {
+            let path = self.root.join(CACHE_FILE_NAME);
+            *cache = Some(at_path(Cache::load(&self.root), &path)?);
+        }
+        f(cache.as_mut().unwrap())
+    }
+
+    fn cached_fingerprint(&self, task_name: &str) -> Result<Option<String>, ExecutionError> {
+        self.with_cache(|cache| Ok(cache.get(task_name).map(str::to_owned)))
+    }
+
+    fn store_fingerprint(&self, task_name: &str, fingerprint: String) -> ExecutionResult {
+        self.with_cache(|cache| {
+            cache.set(task_name, fingerprint);
+            at_path(cache.save(), cache.path())
+        })
+    }
+}
+
+/// Execute some tasks, each after executing the tasks it needs. Each task is
+/// run at most once, and every parameter is checked before anything is
+/// executed.
+///
+/// Unless the tasks are run in parallel or with `keep_going`, execution stops
+/// at the first task that fails. Otherwise every task which doesn't need a
+/// failed one is run, and all of the errors are returned.
+pub fn exec_tasks(
+    invocations: &[Invocation],
+    program: &compiler::Program,
+    working_dir: &Path,
+    options: &Options,
+) -> ExecutionResult {
+    let bindings = bind_invocations(invocations, &program.tasks)?;
+    let run = Run {
+        tasks: &program.tasks,
+        bindings,
+        states: Mutex::new(HashMap::new()),
+        state_changed: Condvar::new(),
+        root: working_dir.to_path_buf(),
+        cache: Mutex::new(None),
+        keep_going: options.keep_going,
+    };
+    let mut ctx = Context::new(working_dir, program.variables.clone());
+    ctx.dry_run = options.dry_run;
+    ctx.output_mode = options.output;
+    if let Some(shell) = &options.shell {
+        ctx.shell = shell.clone();
+    }
+    if let Some(jobs) = options.jobs {
+        ctx.job_slots = JobSlots::new(jobs);
+    }
+    if options.parallel {
+        // Each invocation gets a thread of its own: one waiting for a task
+        // which another is running mustn't hold up anything else.
+        let (run, ctx) = (&run, &ctx);
+        let results: Vec<ExecutionResult> = thread::scope(|scope| {
+            let threads: Vec<_> = invocations
+                .iter()
+                .map(|invocation| {
+                    let task_ctx = ctx.branch(&invocation.task);
+                    scope.spawn(move || {
+                        let result = run.exec_task_once(&invocation.task, &task_ctx);
+                        task_ctx.finish_branch();
+                        result
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| {
+                    thread
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .collect()
+        });
+        collect_errors(results)
+    } else if options.keep_going {
+        let results: Vec<ExecutionResult> = invocations
+            .iter()
+            .map(|invocation| run.exec_task_once(&invocation.task, &ctx))
+            .collect();
+        collect_errors(results)
+    } else {
+        for invocation in invocations {
+            run.exec_task_once(&invocation.task, &ctx)?;
+        }
+        Ok(())
+    }
+}
+
+/// Execute a task, after executing the tasks it needs. Each task is run at
+/// most once.
+pub fn exec_task(
+    task_name: &str,
+    program: &compiler::Program,
+    working_dir: &Path,
+) -> ExecutionResult {
+    exec_task_with_args(task_name, &[], program, working_dir)
+}
+
+/// Execute a task with arguments for its parameters (see `bind_params`).
+pub fn exec_task_with_args(
+    task_name: &str,
+    args: &[String],
+    program: &compiler::Program,
+    working_dir: &Path,
+) -> ExecutionResult {
+    let invocation = Invocation {
+        task: task_name.to_owned(),
+        args: args.to_vec(),
+    };
+    exec_tasks(&[invocation], program, working_dir, &Options::default())
+}
@@ -0,0 +1,209 @@
+//! Helpers for the files that tasks read and write.
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Find the paths matching a glob pattern, which is relative to `dir` unless
+/// it's absolute. As in a shell, a wildcard doesn't match a leading `.`, so
+/// `*` leaves dotfiles (and `.git`) alone.
+#[cfg(feature = "glob")]
+pub fn expand_pattern(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let full_pattern = if Path::new(pattern).is_absolute() {
+        pattern.to_owned()
+    } else {
+        let dir_str = dir
+            .to_str()
+            .ok_or_else(|| format!("'{}' isn't valid utf-8", dir.display()))?;
+        format!("{}/{}", glob::Pattern::escape(dir_str), pattern)
+    };
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..glob::MatchOptions::new()
+    };
+    let paths = glob::glob_with(&full_pattern, &options)
+        .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?;
+    let mut matches = Vec::new();
+    for path in paths {
+        matches.push(path.map_err(|e| e.to_string())?);
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+/// Without the `glob` feature, patterns are literal paths.
+#[cfg(not(feature = "glob"))]
+pub fn expand_pattern(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let path = dir.join(pattern);
+    if path.exists() {
+        Ok(vec![path])
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Resolve the `.` and `..` components of a path, without looking at the
+/// file system.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !normal.pop() {
+                    normal.push(component);
+                }
+            }
+            _ => normal.push(component),
+        }
+    }
+    normal
+}
+
+/// Resolve a path to an absolute one without `.`, `..` or symlinks, except
+/// that a symlink at its end is left alone (so that it's the link which is
+/// acted on, not what it points to). The path doesn't have to exist.
+pub fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let name = match path.file_name() {
+        Some(name) => name,
+        None => return path.canonicalize(),
+    };
+    let mut missing = Vec::new();
+    let mut existing = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut resolved = loop {
+        let dir = if existing.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            existing
+        };
+        match dir.canonicalize() {
+            Ok(resolved) => break resolved,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                match (existing.parent(), existing.file_name()) {
+                    (Some(parent), Some(name)) => {
+                        missing.push(name);
+                        existing = parent;
+                    }
+                    _ => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    for name in missing.iter().rev() {
+        resolved.push(name);
+    }
+    resolved.push(name);
+    Ok(normalize(&resolved))
+}
+
+/// The version control repository (Git, Mercurial or Subversion) a directory
+/// is in, if it's in one.
+pub fn repository_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors()
+        .find(|d| [".git", ".hg", ".svn"].iter().any(|m| d.join(m).exists()))
+}
+
+/// Whether every output was modified after every source. With no outputs,
+/// nothing is up to date.
+pub fn is_up_to_date(sources: &[PathBuf], outputs: &[PathBuf]) -> io::Result<bool> {
+    let mut oldest_output = None;
+    for output in outputs {
+        let modified = output.metadata()?.modified()?;
+        oldest_output = Some(match oldest_output {
+            Some(oldest) if oldest < modified => oldest,
+            _ => modified,
+        });
+    }
+    let oldest_output = match oldest_output {
+        Some(o) => o,
+        None => return Ok(false),
+    };
+    for source in sources {
+        if source.metadata()?.modified()? >= oldest_output {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[test]
+fn test_normalize() {
+    let cases = [
+        ("/project/./dist/../target", "/project/target"),
+        ("/project/..", "/"),
+        ("dist/", "dist"),
+        ("../outside", "../outside"),
+    ];
+    for (path, expected) in &cases {
+        assert_eq!(normalize(Path::new(path)), Path::new(expected), "{}", path);
+    }
+}
+
+#[test]
+fn test_resolve() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join("rhiz-test-resolve");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("project/dist")).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let cases = [
+        ("project/dist", "project/dist"),
+        ("project/./dist/../missing/new", "project/missing/new"),
+        ("project/..", ""),
+    ];
+    for (path, expected) in &cases {
+        assert_eq!(
+            resolve(&dir.join(path)).unwrap(),
+            normalize(&dir.join(expected)),
+            "{}",
+            path
+        );
+    }
+    // Like the file system, `..` can't go up from a directory which doesn't
+    // exist.
+    assert!(resolve(&dir.join("project/missing/../other")).is_err());
+    #[cfg(unix)]
+    {
+        // A symlink at the end is kept; one before it is followed.
+        std::os::unix::fs::symlink(dir.join("project/dist"), dir.join("link")).unwrap();
+        assert_eq!(resolve(&dir.join("link")).unwrap(), dir.join("link"));
+        assert_eq!(
+            resolve(&dir.join("link/app.js")).unwrap(),
+            dir.join("project/dist/app.js")
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_is_up_to_date() {
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    let dir = std::env::temp_dir().join("rhiz-test-is-up-to-date");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let sources = [dir.join("source")];
+    let outputs = [dir.join("output")];
+    fs::write(&sources[0], "source").unwrap();
+    fs::write(&outputs[0], "output").unwrap();
+    let now = SystemTime::now();
+    let set_modified = |path: &Path, time: SystemTime| {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap()
+    };
+
+    set_modified(&sources[0], now - Duration::from_secs(60));
+    set_modified(&outputs[0], now);
+    assert!(is_up_to_date(&sources, &outputs).unwrap());
+    assert!(is_up_to_date(&[], &outputs).unwrap());
+    assert!(!is_up_to_date(&sources, &[]).unwrap());
+
+    set_modified(&sources[0], now + Duration::from_secs(60));
+    assert!(!is_up_to_date(&sources, &outputs).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,267 +1,1447 @@
+use std::collections::HashMap;
 use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-
-use rayon::prelude::*;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::ast::RhizValue;
-use crate::executor::{exec_sexpr, ExecutionError, ExecutionResult};
+use crate::compiler::split_form;
+use crate::conditions;
+use crate::executor::{
+    at_path, collect_errors, combine_results, exec_sexpr, exec_sexprs, Context, ErrorKind,
+    ExecutionError, ExecutionResult,
+};
+use crate::files;
+use crate::timeout::{self, Deadline};
+use crate::variables;
 
-type RhizFunction = dyn Fn(&[RhizValue], &Path) -> ExecutionResult;
+type RhizFunction = dyn Fn(&[RhizValue], &Context) -> ExecutionResult;
 
 macro_rules! error_with {
-    ($msg:expr $(, $p:expr)* ) => {
-        {
-            let msg = format!($msg$(, $p)*);
-            return Err(ExecutionError::from(msg));
-        }
+    ( $kind:expr ) => {
+        return Err(ExecutionError::from($kind))
     };
 }
 
 macro_rules! check_args_len {
     ( $fname:expr, $args:expr, $cnt:expr ) => {
         if $args.len() != $cnt {
-            error_with!("`{}` takes {} argument(s)", $fname, $cnt)
+            error_with!(ErrorKind::Arity {
+                function: $fname.to_owned(),
+                min: $cnt,
+                max: Some($cnt),
+            })
         }
     };
 }
 
+/// Get a string argument, with its variable references substituted.
 macro_rules! get_arg {
-    ( $fname:expr, $args:expr, $idx:expr, $kind:path) => {{
+    ( $fname:expr, $args:expr, $idx:expr, $ctx:expr) => {{
         let arg = match $args.get($idx) {
             Some(a) => a,
-            None => error_with!("Expected `{}` to have at least {} arguments", $fname, $idx),
+            None => error_with!(ErrorKind::Arity {
+                function: $fname.to_owned(),
+                min: $idx + 1,
+                max: None,
+            }),
         };
-        if let $kind(v) = arg {
-            v
+        if let RhizValue::String(_, _) = arg {
+            $ctx.expand(arg)?
         } else {
-            error_with!(
-                "Expected argument {} to `{}` to be a {}",
-                $idx,
-                $fname,
-                stringify!($kind)
-            );
+            let kind = ErrorKind::ArgumentType {
+                function: $fname.to_owned(),
+                index: $idx,
+                expected: "a string",
+            };
+            return Err(ExecutionError::new(kind).located(arg.position()));
         }
     }};
 }
 
-pub fn look_up_function(func_name: &RhizValue) -> Option<Box<RhizFunction>> {
+/// Options given to a function as leading `:name [value]` arguments.
+struct FnOptions<'a> {
+    /// Each option's name (without the `:`), keyword and value.
+    given: Vec<(&'a str, &'a RhizValue, Option<&'a RhizValue>)>,
+}
+
+impl<'a> FnOptions<'a> {
+    /// Split a function's leading options from the rest of its arguments.
+    /// `spec` lists the options the function accepts, and whether each takes
+    /// a value.
+    fn take(
+        fname: &str,
+        args: &'a [RhizValue],
+        spec: &[(&str, bool)],
+    ) -> Result<(FnOptions<'a>, &'a [RhizValue]), ExecutionError> {
+        let mut given = Vec::new();
+        let mut idx = 0;
+        while let Some(keyword @ RhizValue::String(s, _)) = args.get(idx) {
+            let name = match s.strip_prefix(':') {
+                Some(name) if !name.is_empty() => name,
+                _ => break,
+            };
+            let takes_value = match spec.iter().find(|(n, _)| *n == name) {
+                Some((_, takes_value)) => *takes_value,
+                None => {
+                    let msg = format!("`{}` doesn't have a `:{}` option", fname, name);
+                    return Err(ExecutionError::invalid(msg).located(keyword.position()));
+                }
+            };
+            if takes_value {
+                match args.get(idx + 1) {
+                    Some(value) => given.push((name, keyword, Some(value))),
+                    None => {
+                        let msg = format!("`:{}` needs a value", name);
+                        return Err(ExecutionError::invalid(msg).located(keyword.position()));
+                    }
+                }
+                idx += 2;
+            } else {
+                given.push((name, keyword, None));
+                idx += 1;
+            }
+        }
+        Ok((FnOptions { given }, &args[idx..]))
+    }
+
+    /// The value of an option, if it was given.
+    fn value(&self, option: &str) -> Option<&'a RhizValue> {
+        self.given
+            .iter()
+            .rev()
+            .find(|(name, _, _)| *name == option)
+            .and_then(|(_, _, value)| *value)
+    }
+
+    /// Whether a flag (an option without a value) was given.
+    fn flag(&self, option: &str) -> bool {
+        self.given.iter().any(|(name, _, _)| *name == option)
+    }
+
+    /// Environment settings from `:env NAME=value` and `:unset NAME` options.
+    fn env_settings(&self) -> Vec<&'a RhizValue> {
+        let mut settings = Vec::new();
+        for (name, keyword, value) in &self.given {
+            match (*name, value) {
+                ("env", Some(v)) => settings.push(*v),
+                ("unset", Some(v)) => {
+                    settings.push(*keyword);
+                    settings.push(*v);
+                }
+                _ => (),
+            }
+        }
+        settings
+    }
+}
+
+/// A function which can be called in a task.
+pub struct Function {
+    run: Box<RhizFunction>,
+    /// Prints what `run` would do without doing it, for dry runs. Functions
+    /// without side effects (or which only execute other expressions) don't
+    /// have one.
+    describe: Option<Box<RhizFunction>>,
+}
+
+impl Function {
+    fn new(run: Box<RhizFunction>) -> Function {
+        Function {
+            run,
+            describe: None,
+        }
+    }
+
+    fn with_description(run: Box<RhizFunction>, describe: Box<RhizFunction>) -> Function {
+        Function {
+            run,
+            describe: Some(describe),
+        }
+    }
+
+    pub fn call(&self, args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+        match &self.describe {
+            Some(describe) if ctx.dry_run => describe(args, ctx),
+            _ => (self.run)(args, ctx),
+        }
+    }
+}
+
+pub fn look_up_function(func_name: &RhizValue) -> Option<Function> {
     let symbol_name = match func_name {
-        RhizValue::String(s) => s,
+        RhizValue::String(s, _) => s,
         _ => return None,
     };
     match symbol_name.as_ref() {
-        "log" => Some(Box::new(log)),
-        "exec" => Some(Box::new(exec)),
-        "empty-dir" => Some(Box::new(empty_dir)),
-        "delete" => Some(Box::new(delete)),
-        "copy" => Some(Box::new(copy)),
-        "rec-copy" => Some(Box::new(rec_copy)),
-        "par" => Some(Box::new(par)),
+        "log" => Some(Function::new(Box::new(log))),
+        "exec" => Some(Function::with_description(
+            Box::new(|args: &[RhizValue], ctx: &Context| exec("exec", args, ctx)),
+            Box::new(|args: &[RhizValue], ctx: &Context| describe_exec("exec", args, ctx)),
+        )),
+        "sh" => Some(Function::with_description(
+            Box::new(|args: &[RhizValue], ctx: &Context| exec("sh", args, ctx)),
+            Box::new(|args: &[RhizValue], ctx: &Context| describe_exec("sh", args, ctx)),
+        )),
+        "empty-dir" => Some(Function::with_description(
+            Box::new(empty_dir),
+            Box::new(describe_empty_dir),
+        )),
+        "delete" => Some(Function::with_description(
+            Box::new(|args: &[RhizValue], ctx: &Context| delete("delete", args, ctx)),
+            Box::new(|args: &[RhizValue], ctx: &Context| describe_delete("delete", args, ctx)),
+        )),
+        "delete-dir" => Some(Function::with_description(
+            Box::new(|args: &[RhizValue], ctx: &Context| delete("delete-dir", args, ctx)),
+            Box::new(|args: &[RhizValue], ctx: &Context| describe_delete("delete-dir", args, ctx)),
+        )),
+        "copy" => Some(Function::with_description(
+            Box::new(copy),
+            Box::new(describe_copy),
+        )),
+        "move" => Some(Function::with_description(
+            Box::new(move_paths),
+            Box::new(describe_move),
+        )),
+        "rec-copy" => Some(Function::with_description(
+            Box::new(rec_copy),
+            Box::new(describe_rec_copy),
+        )),
+        "par" => Some(Function::new(Box::new(par))),
+        "if" => Some(Function::new(Box::new(if_else))),
+        "when" => Some(Function::new(Box::new(
+            |args: &[RhizValue], ctx: &Context| exec_when("when", true, args, ctx),
+        ))),
+        "unless" => Some(Function::new(Box::new(
+            |args: &[RhizValue], ctx: &Context| exec_when("unless", false, args, ctx),
+        ))),
+        "try" => Some(Function::new(Box::new(try_catch))),
+        "ignore-errors" => Some(Function::new(Box::new(ignore_errors))),
+        "retry" => Some(Function::new(Box::new(retry))),
+        "in-dir" => Some(Function::new(Box::new(in_dir))),
         _ => None,
     }
 }
 
-/// Execute tasks in parallel
-/// (par (some other task), (some other task))
-fn par(args: &[RhizValue], cwd: &Path) -> ExecutionResult {
-    args.par_iter()
-        .map(|arg| match arg {
-            RhizValue::SExpr(x) => exec_sexpr(x, cwd),
-            _ => error_with!("`par` needs sexprs!"),
+/// Execute tasks in parallel, reporting the errors of all of those that fail.
+/// Each one has its own thread; the number of external commands they run at
+/// once is limited by the job slots (and by `:jobs`, if it's given). Their
+/// output is shown according to the output mode (or `:output`), labelled
+/// as `branch_labels` describes.
+/// (par [:jobs n] [:output mode] (some other task), (some other task))
+fn par(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let (options, args) = FnOptions::take("par", args, &[("jobs", true), ("output", true)])?;
+    let mut ctx = ctx.clone();
+    if let Some(value) = options.value("jobs") {
+        let jobs = match ctx.expand(value)?.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => jobs,
+            _ => {
+                let msg = "`:jobs` should be a positive number";
+                return Err(ExecutionError::invalid(msg).located(value.position()));
+            }
+        };
+        ctx = ctx.with_job_limit(jobs);
+    }
+    if let Some(value) = options.value("output") {
+        ctx.output_mode = ctx
+            .expand(value)?
+            .parse()
+            .map_err(|msg: String| ExecutionError::invalid(msg).located(value.position()))?;
+    }
+    let ctx = &ctx;
+    let labels = branch_labels(args, ctx);
+    let results: Vec<ExecutionResult> = thread::scope(|scope| {
+        let branches: Vec<_> =
+            args.iter()
+                .zip(&labels)
+                .map(|(arg, label)| {
+                    let branch_ctx = ctx.branch(label);
+                    scope.spawn(move || {
+                        let result = match arg {
+                            RhizValue::SExpr(_, _) => exec_sexpr(arg, &branch_ctx),
+                            _ => Err(ExecutionError::invalid("`par` needs sexprs!")
+                                .located(arg.position())),
+                        };
+                        branch_ctx.finish_branch();
+                        result
+                    })
+                })
+                .collect();
+        branches
+            .into_iter()
+            .map(|branch| {
+                branch
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect()
+    });
+    collect_errors(results)
+}
+
+/// Labels for the output of `par`'s branches: the name of the program an
+/// `exec` or `sh` runs, or else the function's name. Branches with the same
+/// label are numbered (e.g. `cc 1`, `cc 2`).
+fn branch_labels(branches: &[RhizValue], ctx: &Context) -> Vec<String> {
+    let labels: Vec<String> = branches.iter().map(|b| branch_label(b, ctx)).collect();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    labels
+        .iter()
+        .map(|label| {
+            if labels.iter().filter(|l| *l == label).count() == 1 {
+                return label.clone();
+            }
+            let number = seen.entry(label).or_insert(0);
+            *number += 1;
+            format!("{} {}", label, number)
         })
-        .fold(
-            || Ok(()),
-            |acc, elem| match (&acc, &elem) {
-                (Ok(_), Ok(_)) => Ok(()),
-                (Err(_), _) => acc,
-                (Ok(_), Err(_)) => elem,
-            },
-        )
         .collect()
 }
 
+fn branch_label(branch: &RhizValue, ctx: &Context) -> String {
+    let (fname, args) = match branch {
+        RhizValue::SExpr(contents, _) => match contents.split_first() {
+            Some((RhizValue::String(fname, _), args)) => (fname.as_str(), args),
+            _ => return "par".to_owned(),
+        },
+        _ => return "par".to_owned(),
+    };
+    // A branch whose command can't be worked out fails when it runs.
+    let program = match fname {
+        "exec" => ExternalCommand::from_args(fname, args, ctx)
+            .ok()
+            .map(|command| command.words[0].clone()),
+        "sh" => ExternalCommand::from_args(fname, args, ctx)
+            .ok()
+            .and_then(|command| {
+                let script = command.words.last()?;
+                script.split_whitespace().next().map(str::to_owned)
+            }),
+        _ => None,
+    };
+    match program {
+        Some(program) => match Path::new(&program).file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => program,
+        },
+        None => fname.to_owned(),
+    }
+}
+
+/// An error about a file or directory a function was given.
+fn bad_path(path: &Path, message: &str) -> ErrorKind {
+    ErrorKind::BadPath {
+        path: path.to_path_buf(),
+        message: message.to_owned(),
+    }
+}
+
+/// Execute one expression or another, depending on a condition.
+/// (if condition then-expression [else-expression])
+fn if_else(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    if !(2..=3).contains(&args.len()) {
+        error_with!(ErrorKind::Arity {
+            function: "if".to_owned(),
+            min: 2,
+            max: Some(3),
+        });
+    }
+    if conditions::evaluate(&args[0], ctx)? {
+        exec_sexpr(&args[1], ctx)
+    } else {
+        match args.get(2) {
+            Some(otherwise) => exec_sexpr(otherwise, ctx),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Execute some expressions if a condition has the `expected` value
+/// (`when` and `unless`).
+/// (when condition expression*)
+fn exec_when(fname: &str, expected: bool, args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let condition = match args.first() {
+        Some(c) => c,
+        None => error_with!(ErrorKind::Arity {
+            function: fname.to_owned(),
+            min: 1,
+            max: None,
+        }),
+    };
+    if conditions::evaluate(condition, ctx)? == expected {
+        exec_sexprs(&args[1..], ctx)?;
+    }
+    Ok(())
+}
+
+/// The parts of a `try`: its body, and the contents of its `catch` and
+/// `finally` clauses.
+pub struct TryForm<'a> {
+    pub body: &'a [RhizValue],
+    pub catch: Option<&'a [RhizValue]>,
+    pub finally: Option<&'a [RhizValue]>,
+}
+
+impl<'a> TryForm<'a> {
+    /// Split a `try`'s arguments into its parts. The clauses are optional, but
+    /// have to come last, in that order.
+    pub fn split(args: &'a [RhizValue]) -> Result<TryForm<'a>, (&'static str, &'a RhizValue)> {
+        let mut body = args;
+        let mut take_clause = |name: &str| match body.split_last() {
+            Some((last, rest)) => match split_form(last) {
+                Some((n, contents)) if n == name => {
+                    body = rest;
+                    Some(contents)
+                }
+                _ => None,
+            },
+            None => None,
+        };
+        let finally = take_clause("finally");
+        let catch = take_clause("catch");
+        if let Some(misplaced) = body
+            .iter()
+            .find(|v| matches!(split_form(v), Some(("catch", _)) | Some(("finally", _))))
+        {
+            let msg = "`catch` and `finally` should come at the end of a `try`, in that order";
+            return Err((msg, misplaced));
+        }
+        Ok(TryForm {
+            body,
+            catch,
+            finally,
+        })
+    }
+}
+
+/// Execute some expressions; if one fails, execute the `catch` clause (with
+/// the error's message in `${error}`). The `finally` clause is executed
+/// either way.
+/// (try expression* [(catch expression*)] [(finally expression*)])
+fn try_catch(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let form = TryForm::split(args)
+        .map_err(|(msg, item)| ExecutionError::invalid(msg).located(item.position()))?;
+    let mut result = exec_sexprs(form.body, ctx);
+    if let (Err(e), Some(catch)) = (&result, form.catch) {
+        let mut error = HashMap::new();
+        error.insert("error".to_owned(), e.to_string());
+        let caught = exec_sexprs(catch, &ctx.with_variables(error));
+        result = match caught {
+            Ok(()) => Ok(()),
+            Err(_) => combine_results(result, caught),
+        };
+    }
+    match form.finally {
+        Some(finally) => combine_results(result, exec_sexprs(finally, ctx)),
+        None => result,
+    }
+}
+
+/// Execute some expressions, carrying on after any which fail (their errors
+/// are printed).
+/// (ignore-errors expression*)
+fn ignore_errors(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    for expr in args {
+        if let Err(e) = exec_sexpr(expr, ctx) {
+            ctx.print_error(&format!("Ignoring error: {}", e));
+        }
+    }
+    Ok(())
+}
+
+/// The parts of a `retry`: how many attempts to make, how long to wait
+/// before the first retry, and the expressions to execute.
+pub struct RetryForm<'a> {
+    pub attempts: &'a RhizValue,
+    pub delay: Option<&'a RhizValue>,
+    pub body: &'a [RhizValue],
+}
+
+impl<'a> RetryForm<'a> {
+    /// Split a `retry`'s (non-empty) arguments into its parts, checking the
+    /// number of attempts and the delay if they don't refer to variables.
+    pub fn split(args: &'a [RhizValue]) -> Result<RetryForm<'a>, (String, &'a RhizValue)> {
+        let (attempts, rest) = args.split_first().expect("`retry` should have arguments");
+        let (delay, body) = match rest.split_first() {
+            Some((delay @ RhizValue::String(_, _), body)) => (Some(delay), body),
+            _ => (None, rest),
+        };
+        let literal = |value: &'a RhizValue| match value {
+            RhizValue::String(s, _) if s.contains('$') => Ok(None),
+            RhizValue::String(s, _) => Ok(Some(s.as_str())),
+            _ => Err(("Expected a string".to_owned(), value)),
+        };
+        if let Some(text) = literal(attempts)? {
+            parse_attempts(text).map_err(|msg| (msg, attempts))?;
+        }
+        if let Some(delay) = delay {
+            if let Some(text) = literal(delay)? {
+                timeout::parse_duration(text).map_err(|msg| (msg, delay))?;
+            }
+        }
+        Ok(RetryForm {
+            attempts,
+            delay,
+            body,
+        })
+    }
+}
+
+fn parse_attempts(text: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "The number of attempts should be a positive number, not '{}'",
+            text
+        )),
+    }
+}
+
+/// Execute some expressions until they all succeed, up to `attempts` times.
+/// Each failed attempt is reported, and the wait before the next one starts
+/// at `delay` (or none) and doubles each time.
+/// (retry attempts [delay] expression*)
+fn retry(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    if args.is_empty() {
+        error_with!(ErrorKind::Arity {
+            function: "retry".to_owned(),
+            min: 1,
+            max: None,
+        });
+    }
+    let form = RetryForm::split(args)
+        .map_err(|(msg, item)| ExecutionError::invalid(msg).located(item.position()))?;
+    let attempts = parse_attempts(&ctx.expand(form.attempts)?)
+        .map_err(|msg| ExecutionError::invalid(msg).located(form.attempts.position()))?;
+    let mut delay = match form.delay {
+        Some(value) => timeout::parse_duration(&ctx.expand(value)?)
+            .map_err(|msg| ExecutionError::invalid(msg).located(value.position()))?,
+        None => Duration::from_secs(0),
+    };
+    let mut attempt = 1;
+    loop {
+        let error = match exec_sexprs(form.body, ctx) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        // Running out of time isn't something to retry.
+        if attempt == attempts || matches!(error.kind(), ErrorKind::TimedOut { .. }) {
+            return Err(error);
+        }
+        ctx.print_error(&format!(
+            "Attempt {} of {} failed: {}",
+            attempt, attempts, error
+        ));
+        // The delay doesn't outlast the time limit.
+        let time_left = match &ctx.deadline {
+            Some(deadline) => deadline.at.saturating_duration_since(Instant::now()),
+            None => delay,
+        };
+        thread::sleep(delay.min(time_left));
+        ctx.check_deadline()?;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Resolve a directory given to `in-dir` or `:cwd` (`fname`), relative to the
+/// context's working directory. In a dry run, it doesn't have to exist yet.
+fn sub_dir(fname: &str, value: &RhizValue, ctx: &Context) -> Result<PathBuf, ExecutionError> {
+    let dir = join_cwd(&ctx.working_dir, &ctx.expand(value)?);
+    if !ctx.dry_run && !dir.is_dir() {
+        let msg = format!("`{}`'s directory doesn't exist", fname);
+        return Err(ExecutionError::new(bad_path(&dir, &msg)).located(value.position()));
+    }
+    Ok(dir)
+}
+
+/// Execute some expressions in a subdirectory, which relative paths (and
+/// external commands) are resolved against.
+/// (in-dir directory expression*)
+fn in_dir(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let dir = match args.first() {
+        Some(dir) => dir,
+        None => error_with!(ErrorKind::Arity {
+            function: "in-dir".to_owned(),
+            min: 1,
+            max: None,
+        }),
+    };
+    let ctx = ctx.in_dir(sub_dir("in-dir", dir, ctx)?);
+    exec_sexprs(&args[1..], &ctx)
+}
+
+/// Check that the working directory exists, since it could have been deleted
+/// after `in-dir` checked it. In a dry run, it doesn't have to exist yet.
+fn check_working_dir(ctx: &Context) -> ExecutionResult {
+    if !ctx.dry_run && !ctx.working_dir.is_dir() {
+        error_with!(bad_path(
+            &ctx.working_dir,
+            "The working directory doesn't exist"
+        ));
+    }
+    Ok(())
+}
+
 fn join_cwd(cwd: &Path, fpath: &str) -> PathBuf {
     let mut cwd = cwd.to_path_buf();
     cwd.push(fpath);
     cwd
 }
 
-fn val_to_string(rval: &RhizValue) -> Option<String> {
-    match rval {
-        RhizValue::String(s) => Some(s.to_owned()),
-        _ => None,
-    }
-}
-
 /// Print a message to the console.
-fn log(args: &[RhizValue], _: &Path) -> ExecutionResult {
+fn log(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
     check_args_len!("log", args, 1);
-    let msg = get_arg!("log", args, 0, RhizValue::String);
+    let msg = get_arg!("log", args, 0, ctx);
 
-    println!("{}", msg);
+    ctx.print(&msg);
 
     Ok(())
 }
 
-/// Execute an external command
-fn exec(args: &[RhizValue], working_dir: &Path) -> ExecutionResult {
-    assert!(working_dir.is_dir());
-    if args.is_empty() {
-        error_with!("`exec` needs at least one argument");
+/// Quote a word of a command line if it has characters a shell would
+/// interpret.
+fn quote_word(word: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c);
+    if !word.is_empty() && word.chars().all(is_plain) {
+        word.to_owned()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
     }
+}
 
-    let cmd_name = val_to_string(&args[0]).ok_or_else(|| ExecutionError::from(
-        "`exec` takes a string or symbol as a command name",
-    ))?;
+/// Where an external command's stdin comes from.
+enum Input {
+    Inherit,
+    /// `:stdin path`
+    File(PathBuf),
+    /// `:input "text"`
+    Text(String),
+}
+
+/// Where an external command's stdout or stderr goes.
+enum Redirect {
+    /// To rhiz's own output.
+    Inherit,
+    /// `:stdout :null`
+    Null,
+    /// `:stdout path` or `:stdout-append path`
+    File { path: PathBuf, append: bool },
+    /// Into a variable (stdout only), with `:capture name`.
+    Capture(String),
+}
 
-    let mut cmd = Command::new(&cmd_name);
-    cmd.current_dir(working_dir);
+impl Redirect {
+    /// Read a redirection of `stream` ("stdout" or "stderr") from the
+    /// `:stream target` and `:stream-append path` options.
+    fn from_options(
+        options: &FnOptions,
+        stream: &str,
+        ctx: &Context,
+    ) -> Result<Redirect, ExecutionError> {
+        let append_option = format!("{}-append", stream);
+        let redirect = match (options.value(stream), options.value(&append_option)) {
+            (Some(_), Some(value)) => {
+                let msg = format!(
+                    "Only one of `:{}` and `:{}` can be given",
+                    stream, append_option
+                );
+                return Err(ExecutionError::invalid(msg).located(value.position()));
+            }
+            (Some(value), None) => match ctx.expand(value)?.as_str() {
+                ":null" => Redirect::Null,
+                path => Redirect::File {
+                    path: ctx.working_dir.join(path),
+                    append: false,
+                },
+            },
+            (None, Some(value)) => Redirect::File {
+                path: ctx.working_dir.join(ctx.expand(value)?),
+                append: true,
+            },
+            (None, None) => Redirect::Inherit,
+        };
+        Ok(redirect)
+    }
 
-    if args.len() > 1 {
-        let mut cmd_args = Vec::new();
-        for arg in args.iter().skip(1) {
-            match val_to_string(arg) {
-                Some(s) => cmd_args.push(s),
-                None => error_with!("`exec` takes strings or symbols as command arguments"),
+    /// How the stream should be set up; `forwarded` is whether output which
+    /// isn't redirected has to be passed to the context's output.
+    fn stdio(&self, forwarded: bool) -> Result<Stdio, ExecutionError> {
+        let stdio = match self {
+            Redirect::Inherit if forwarded => Stdio::piped(),
+            Redirect::Inherit => Stdio::inherit(),
+            Redirect::Null => Stdio::null(),
+            Redirect::File { path, append } => {
+                let file = fs::OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(*append)
+                    .truncate(!*append)
+                    .open(path);
+                Stdio::from(at_path(file, path)?)
             }
+            Redirect::Capture(_) => Stdio::piped(),
+        };
+        Ok(stdio)
+    }
+
+    /// The redirection written like a shell's, e.g. `2>> log.txt`.
+    fn describe(&self, fd: &str) -> Option<String> {
+        match self {
+            Redirect::Inherit | Redirect::Capture(_) => None,
+            Redirect::Null => Some(format!("{}> /dev/null", fd)),
+            Redirect::File { path, append } => Some(format!(
+                "{}{} {}",
+                fd,
+                if *append { ">>" } else { ">" },
+                quote_word(&path.display().to_string())
+            )),
         }
-        cmd.args(&cmd_args);
     }
+}
+
+/// An external command, with its arguments and environment resolved.
+struct ExternalCommand {
+    /// The command's name, followed by its arguments.
+    words: Vec<String>,
+    env: Vec<(String, Option<String>)>,
+    working_dir: PathBuf,
+    /// When the command has to finish by, from its `:timeout` or the
+    /// context's.
+    deadline: Option<Deadline>,
+    stdin: Input,
+    stdout: Redirect,
+    stderr: Redirect,
+}
 
-    let mut child_process = cmd.spawn()?;
-    let exit_code = child_process.wait()?;
-    if !exit_code.success() {
-        let mut cmd_string = String::new();
-        cmd_string.push_str(&String::from(&args[0]));
-        for arg in &args[1..] {
-            cmd_string.push(' ');
-            cmd_string.push_str(&String::from(arg));
+impl ExternalCommand {
+    /// Resolve the arguments of an `exec`, or of an `sh` (whose script is
+    /// passed to the shell).
+    fn from_args(
+        fname: &str,
+        args: &[RhizValue],
+        ctx: &Context,
+    ) -> Result<ExternalCommand, ExecutionError> {
+        let mut spec = vec![
+            ("env", true),
+            ("unset", true),
+            ("cwd", true),
+            ("timeout", true),
+            ("stdin", true),
+            ("input", true),
+            ("stdout", true),
+            ("stdout-append", true),
+            ("stderr", true),
+            ("stderr-append", true),
+            ("capture", true),
+        ];
+        if fname == "sh" {
+            spec.push(("shell", true));
+        }
+        let (options, args) = FnOptions::take(fname, args, &spec)?;
+        let mut ctx = ctx.with_env(&options.env_settings())?;
+        if let Some(value) = options.value("cwd") {
+            ctx = ctx.in_dir(sub_dir(":cwd", value, &ctx)?);
         }
-        let code_msg = match exit_code.code() {
-            None => String::new(),
-            Some(c) => format!("(exit code: {})", c),
+        let ctx = &ctx;
+        let words = if fname == "sh" {
+            check_args_len!(fname, args, 1);
+            let shell = match options.value("shell") {
+                Some(value) => ctx.expand(value)?,
+                None => ctx.shell.clone(),
+            };
+            let mut words: Vec<String> = shell.split_whitespace().map(str::to_owned).collect();
+            if words.is_empty() {
+                let msg = "The shell should be a program and its options, e.g. \"bash -c\"";
+                error_with!(ErrorKind::Invalid(msg.to_owned()));
+            }
+            words.push(get_arg!(fname, args, 0, ctx));
+            words
+        } else {
+            if args.is_empty() {
+                error_with!(ErrorKind::Arity {
+                    function: fname.to_owned(),
+                    min: 1,
+                    max: None,
+                });
+            }
+            let mut words = Vec::new();
+            for (idx, arg) in args.iter().enumerate() {
+                match arg {
+                    RhizValue::String(_, _) => words.push(ctx.expand(arg)?),
+                    _ => {
+                        let kind = ErrorKind::ArgumentType {
+                            function: fname.to_owned(),
+                            index: idx,
+                            expected: "a string or symbol",
+                        };
+                        return Err(ExecutionError::new(kind).located(arg.position()));
+                    }
+                }
+            }
+            words
         };
 
-        error_with!(
-            "External command returned an error code: {} {}",
-            cmd_string,
-            code_msg
-        );
+        let deadline = match options.value("timeout") {
+            Some(value) => {
+                let limit = timeout::parse_duration(&ctx.expand(value)?)
+                    .map_err(|msg| ExecutionError::invalid(msg).located(value.position()))?;
+                let line: Vec<String> = words.iter().map(|w| quote_word(w)).collect();
+                let what = format!("Command '{}'", line.join(" "));
+                ctx.with_timeout(limit, what).deadline
+            }
+            None => ctx.deadline.clone(),
+        };
+
+        let stdin = match (options.value("stdin"), options.value("input")) {
+            (Some(_), Some(value)) => {
+                let msg = "Only one of `:stdin` and `:input` can be given";
+                return Err(ExecutionError::invalid(msg).located(value.position()));
+            }
+            (Some(value), None) => Input::File(ctx.working_dir.join(ctx.expand(value)?)),
+            (None, Some(value)) => Input::Text(ctx.expand(value)?),
+            (None, None) => Input::Inherit,
+        };
+        let mut stdout = Redirect::from_options(&options, "stdout", ctx)?;
+        if let Some(value) = options.value("capture") {
+            let name = ctx.expand(value)?;
+            let msg = if !matches!(stdout, Redirect::Inherit) {
+                Some("`:capture` can't be given with `:stdout`".to_owned())
+            } else if !variables::is_valid_name(&name) {
+                Some(format!("Invalid variable name: '{}'", name))
+            } else {
+                None
+            };
+            if let Some(msg) = msg {
+                return Err(ExecutionError::invalid(msg).located(value.position()));
+            }
+            stdout = Redirect::Capture(name);
+        }
+        let stderr = Redirect::from_options(&options, "stderr", ctx)?;
+
+        Ok(ExternalCommand {
+            words,
+            env: ctx.env.clone(),
+            working_dir: ctx.working_dir.clone(),
+            deadline,
+            stdin,
+            stdout,
+            stderr,
+        })
+    }
+
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.words[0]);
+        cmd.args(&self.words[1..]);
+        cmd.current_dir(&self.working_dir);
+        for (name, value) in &self.env {
+            match value {
+                Some(v) => cmd.env(name, v),
+                None => cmd.env_remove(name),
+            };
+        }
+        cmd
+    }
+
+    /// The command's name and arguments, quoted like a shell command.
+    fn command_line(&self) -> String {
+        let words: Vec<String> = self.words.iter().map(|w| quote_word(w)).collect();
+        words.join(" ")
+    }
+
+    /// The command line, prefixed with its environment changes in the style
+    /// of `env(1)` and followed by its redirections.
+    fn describe(&self) -> String {
+        let mut words = Vec::new();
+        if !self.env.is_empty() {
+            words.push("env".to_owned());
+        }
+        for (name, value) in &self.env {
+            match value {
+                Some(v) => words.push(quote_word(&format!("{}={}", name, v))),
+                None => {
+                    words.push("-u".to_owned());
+                    words.push(quote_word(name));
+                }
+            }
+        }
+        words.push(self.command_line());
+        match &self.stdin {
+            Input::Inherit => (),
+            Input::File(path) => {
+                words.push(format!("< {}", quote_word(&path.display().to_string())))
+            }
+            Input::Text(text) => words.push(format!("<<< {}", quote_word(text))),
+        }
+        words.extend(self.stdout.describe(""));
+        words.extend(self.stderr.describe("2"));
+        if let Redirect::Capture(name) = &self.stdout {
+            words.push(format!("(output captured in ${{{}}})", name));
+        }
+        words.join(" ")
+    }
+}
+
+/// Execute an external command (`exec`), or a script with the shell (`sh`).
+fn exec(fname: &str, args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    check_working_dir(ctx)?;
+    let external = ExternalCommand::from_args(fname, args, ctx)?;
+
+    let mut command = external.command();
+    let forwarded = ctx.output.captures();
+    command.stdin(match &external.stdin {
+        Input::Inherit => Stdio::inherit(),
+        Input::File(path) => Stdio::from(at_path(fs::File::open(path), path)?),
+        Input::Text(_) => Stdio::piped(),
+    });
+    command.stdout(external.stdout.stdio(forwarded)?);
+    command.stderr(external.stderr.stdio(forwarded)?);
+
+    let _slot = ctx.job_slots.acquire();
+    // A command with a time limit gets its own process group, so that the
+    // processes it starts are killed with it (rhiz passes Ctrl-C on to the
+    // group). Others stay in rhiz's group, so that they get the terminal's
+    // Ctrl-C, and so do ones which could read from the terminal: a
+    // background group is stopped when it tries to.
+    let reads_terminal = matches!(external.stdin, Input::Inherit) && io::stdin().is_terminal();
+    let spawned = match external.deadline {
+        Some(_) if !reads_terminal => timeout::spawn_in_group(&mut command),
+        _ => command.spawn(),
+    };
+    let mut child_process = spawned.map_err(|source| {
+        ExecutionError::new(ErrorKind::Spawn {
+            command: external.words[0].clone(),
+            source,
+        })
+    })?;
+    // Only the streams which are piped are there to take.
+    let stdin = child_process.stdin.take();
+    let (mut stdout, stderr) = (child_process.stdout.take(), child_process.stderr.take());
+    let captured_stdout = match external.stdout {
+        Redirect::Capture(_) => stdout.take(),
+        _ => None,
+    };
+    // Once the command exits, its output is forwarded until there's nothing
+    // left to read, rather than until whatever it left running exits.
+    let exited = AtomicBool::new(false);
+    let (exit_status, output) = thread::scope(|scope| {
+        if let (Some(mut stdin), Input::Text(text)) = (stdin, &external.stdin) {
+            // A command which exits without reading it all is fine.
+            scope.spawn(move || stdin.write_all(text.as_bytes()));
+        }
+        let capture = captured_stdout.map(|mut out| {
+            scope.spawn(move || {
+                let mut output = Vec::new();
+                out.read_to_end(&mut output).map(|_| output)
+            })
+        });
+        if stdout.is_some() || stderr.is_some() {
+            scope.spawn(|| ctx.output.forward(stdout, stderr, &exited));
+        }
+        let exit_status = match &external.deadline {
+            Some(deadline) => timeout::wait_with_deadline(&mut child_process, deadline),
+            None => child_process.wait().map(Some),
+        };
+        exited.store(true, Ordering::SeqCst);
+        let exit_status = exit_status?;
+        let output = match capture {
+            Some(reader) => Some(
+                reader
+                    .join()
+                    .expect("reading a command's output panicked")?,
+            ),
+            None => None,
+        };
+        io::Result::Ok((exit_status, output))
+    })?;
+    let exit_status = match (exit_status, &external.deadline) {
+        (Some(status), _) => status,
+        (None, Some(deadline)) => error_with!(ErrorKind::TimedOut {
+            what: deadline.what.clone(),
+            limit: deadline.limit,
+        }),
+        (None, None) => unreachable!("only commands with a deadline are killed"),
+    };
+    if !exit_status.success() {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&exit_status);
+        #[cfg(not(unix))]
+        let signal = None;
+        error_with!(ErrorKind::CommandFailed {
+            command: external.command_line(),
+            code: exit_status.code(),
+            signal,
+        });
+    }
+    if let (Redirect::Capture(name), Some(output)) = (&external.stdout, output) {
+        let output = String::from_utf8_lossy(&output);
+        ctx.set_variable(name, output.trim_end_matches(['\n', '\r']));
     }
 
     Ok(())
 }
 
-/// If a directory exists, empty it. If it doesn't, create it (and its parents, if necessary).
-fn empty_dir(args: &[RhizValue], working_dir: &Path) -> ExecutionResult {
-    assert!(working_dir.is_dir());
-    if args.is_empty() {
-        error_with!("`empty-dir` needs an argument");
+fn describe_exec(fname: &str, args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let external = ExternalCommand::from_args(fname, args, ctx)?;
+    ctx.print(&format!("{}: {}", fname, external.describe()));
+    // Later commands are described with a reference to the output.
+    if let Redirect::Capture(name) = &external.stdout {
+        ctx.set_variable(name, &format!("${{{}}}", name));
     }
-    let dpath = get_arg!("empty-dir", args, 0, RhizValue::String);
+    Ok(())
+}
 
-    let target_path = join_cwd(working_dir, dpath);
+/// If a directory exists, empty it. If it doesn't, create it (and its parents, if necessary).
+fn empty_dir(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let target_path = empty_dir_target(args, ctx)?;
 
+    if is_symlink(&target_path) {
+        error_with!(bad_path(&target_path, "`empty-dir` won't follow a symlink"));
+    }
     match (target_path.exists(), target_path.is_dir()) {
         (false, _) => {
-            fs::create_dir_all(target_path)?;
+            at_path(fs::create_dir_all(&target_path), &target_path)?;
         }
         (true, true) => {
-            let contents = fs::read_dir(target_path)?;
+            let contents = at_path(fs::read_dir(&target_path), &target_path)?;
             for child_r in contents {
-                let child = child_r?;
-                let meta = child.metadata()?;
-                if meta.is_dir() {
-                    fs::remove_dir_all(child.path())?;
+                let child = at_path(child_r, &target_path)?;
+                let child_path = child.path();
+                let meta = at_path(child.metadata(), &child_path)?;
+                if meta.file_type().is_symlink() {
+                    remove_symlink(&child_path)?;
+                } else if meta.is_dir() {
+                    at_path(fs::remove_dir_all(&child_path), &child_path)?;
                 } else if meta.is_file() {
-                    fs::remove_file(child.path())?;
+                    at_path(fs::remove_file(&child_path), &child_path)?;
                 } else {
-                    error_with!("'{}' isn't a directory or a file?", child.path().display());
+                    error_with!(bad_path(&child_path, "Not a directory or a file"));
                 }
             }
         }
         (true, false) => {
-            error_with!("`delete-dir` can't operate on a file");
+            error_with!(bad_path(
+                &target_path,
+                "`empty-dir` can't operate on a file"
+            ));
         }
     };
 
     Ok(())
 }
 
-/// Delete a file (by absolute path, or path relative to the Rhizfile).
-fn delete(args: &[RhizValue], working_dir: &Path) -> ExecutionResult {
-    assert!(working_dir.is_dir());
-    check_args_len!("delete", args, 1);
-    let fpath = get_arg!("delete", args, 0, RhizValue::String);
+/// Whether a path is a symlink (rather than what it points to).
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
 
-    let target_path = join_cwd(working_dir, fpath);
+fn empty_dir_target(args: &[RhizValue], ctx: &Context) -> Result<PathBuf, ExecutionError> {
+    let working_dir = ctx.working_dir.as_path();
+    check_working_dir(ctx)?;
+    let (options, args) = FnOptions::take("empty-dir", args, &[("allow-outside", false)])?;
+    check_args_len!("empty-dir", args, 1);
+    let dpath = get_arg!("empty-dir", args, 0, ctx);
 
-    if target_path.exists() {
-        fs::remove_file(target_path)?;
-    }
+    let target_path = join_cwd(working_dir, &dpath);
+    check_inside(
+        "empty-dir",
+        &target_path,
+        Within::RhizfileDir,
+        options.flag("allow-outside"),
+        ctx,
+    )?;
+    Ok(target_path)
+}
 
+fn describe_empty_dir(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let target_path = empty_dir_target(args, ctx)?;
+    ctx.print(&format!("empty-dir: {}", target_path.display()));
     Ok(())
 }
 
-/// Copy a file (won't overwrite an existing file).
-fn copy(args: &[RhizValue], working_dir: &Path) -> ExecutionResult {
-    assert!(working_dir.is_dir());
-    check_args_len!("copy", args, 2);
+/// Whether a path argument is a glob pattern rather than a plain path.
+fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
 
-    let src = get_arg!("copy", args, 0, RhizValue::String);
-    let target = get_arg!("copy", args, 1, RhizValue::String);
+/// Whether a path is the Rhizfile, which patterns never match (it can only be
+/// given as a plain path).
+fn is_rhizfile(path: &Path, ctx: &Context) -> bool {
+    path.parent() == Some(ctx.root.as_path())
+        && matches!(
+            path.file_name().and_then(|name| name.to_str()),
+            Some("Rhizfile") | Some("rhizfile")
+        )
+}
 
-    let src_path = Path::new(src);
-    if !(src_path.exists()) {
-        error_with!("`copy`'s source argument ({}) doesn't exist", src);
+/// The paths a file function acts on: those matching a glob pattern (other
+/// than the Rhizfile), or a plain path (whether or not it exists).
+struct Targets {
+    pattern: String,
+    /// Whether `pattern` was expanded as a glob.
+    globbed: bool,
+    paths: Vec<PathBuf>,
+}
+
+impl Targets {
+    /// Resolve a path or pattern relative to the working directory. A path
+    /// which exists is taken literally, even if it looks like a pattern (e.g.
+    /// `a[1].txt`). With `must_match`, it's an error for nothing to match (or
+    /// for a plain path not to exist).
+    fn find(
+        fname: &str,
+        pattern: &str,
+        must_match: bool,
+        ctx: &Context,
+    ) -> Result<Targets, ExecutionError> {
+        let literal = join_cwd(&ctx.working_dir, pattern);
+        let globbed = is_pattern(pattern) && fs::symlink_metadata(&literal).is_err();
+        let paths = if globbed {
+            let mut paths = files::expand_pattern(&ctx.working_dir, pattern)
+                .map_err(ExecutionError::invalid)?;
+            paths.retain(|p| !is_rhizfile(p, ctx));
+            paths
+        } else {
+            vec![literal.clone()]
+        };
+        if must_match && !paths.iter().any(|p| p.exists()) {
+            let msg = format!("`{}`'s `:must-match` pattern doesn't match anything", fname);
+            error_with!(bad_path(&literal, &msg));
+        }
+        Ok(Targets {
+            pattern: pattern.to_owned(),
+            globbed,
+            paths,
+        })
     }
-    if !(src_path.is_file()) {
-        error_with!("`copy` only acts on files ({} is not a file)", src);
+
+    /// Print what a glob pattern matched (plain paths go without saying).
+    fn report(&self, fname: &str, ctx: &Context) {
+        if !self.globbed {
+            return;
+        }
+        if self.paths.is_empty() {
+            ctx.print(&format!("{}: '{}' matches nothing", fname, self.pattern));
+            return;
+        }
+        let paths: Vec<String> = self
+            .paths
+            .iter()
+            .map(|p| {
+                p.strip_prefix(&ctx.working_dir)
+                    .unwrap_or(p)
+                    .display()
+                    .to_string()
+            })
+            .collect();
+        ctx.print(&format!(
+            "{}: '{}' matches {}",
+            fname,
+            self.pattern,
+            paths.join(", ")
+        ));
     }
+}
+
+/// Delete a file (by absolute path, or path relative to the Rhizfile), or the
+/// files matching a glob pattern. With `:recursive` (or as `delete-dir`,
+/// which only deletes directories), directories are deleted along with their
+/// contents. Paths which don't exist are skipped, and symlinks are deleted
+/// rather than what they point to.
+/// (delete [:must-match] [:recursive] [:allow-outside] path-or-pattern)
+/// (delete-dir [:must-match] [:allow-outside] path-or-pattern)
+fn delete(fname: &str, args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let (targets, recursive) = delete_targets(fname, args, ctx)?;
+    targets.report(fname, ctx);
 
-    let target_path_buf = {
-        let arg_path = Path::new(target);
-        if arg_path.exists() && arg_path.is_dir() {
-            let mut t = arg_path.to_path_buf();
-            let target_filename = src_path
-                .file_name()
-                .ok_or_else(|| ExecutionError::from("`copy` source doesn't have a file name?"))?;
-            t.push(target_filename);
-            t
+    for target_path in &targets.paths {
+        // A symlink is deleted itself, rather than what it points to.
+        let meta = match fs::symlink_metadata(target_path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(ExecutionError::io(target_path, e)),
+        };
+        if meta.file_type().is_symlink() {
+            remove_symlink(target_path)?;
+        } else if meta.is_dir() && recursive {
+            at_path(fs::remove_dir_all(target_path), target_path)?;
+        } else if fname == "delete-dir" {
+            error_with!(bad_path(
+                target_path,
+                "`delete-dir` only acts on directories"
+            ));
         } else {
-            arg_path.to_owned()
+            at_path(fs::remove_file(target_path), target_path)?;
         }
+    }
+
+    Ok(())
+}
+
+/// Resolve what a `delete` or `delete-dir` acts on, and whether it deletes
+/// directories.
+fn delete_targets(
+    fname: &str,
+    args: &[RhizValue],
+    ctx: &Context,
+) -> Result<(Targets, bool), ExecutionError> {
+    check_working_dir(ctx)?;
+    let mut spec = vec![("must-match", false), ("allow-outside", false)];
+    if fname == "delete" {
+        spec.push(("recursive", false));
+    }
+    let (options, args) = FnOptions::take(fname, args, &spec)?;
+    check_args_len!(fname, args, 1);
+    let pattern = get_arg!(fname, args, 0, ctx);
+
+    let recursive = fname == "delete-dir" || options.flag("recursive");
+    let within = if recursive {
+        Within::RhizfileDir
+    } else {
+        Within::Repository
     };
-    let target_path = target_path_buf.as_path();
-    assert!(!target_path.is_dir());
-    if target_path.exists() {
-        error_with!("`copy` won't clobber an existing file ({} exists)", target);
+    let targets = Targets::find(fname, &pattern, options.flag("must-match"), ctx)?;
+    for target_path in &targets.paths {
+        check_inside(
+            fname,
+            target_path,
+            within,
+            options.flag("allow-outside"),
+            ctx,
+        )?;
     }
+    Ok((targets, recursive))
+}
 
-    fs::copy(src_path, target_path)?;
+/// Where a destructive function can act without `:allow-outside`.
+#[derive(Clone, Copy)]
+enum Within {
+    /// The Rhizfile's directory, for the functions which delete whole trees.
+    RhizfileDir,
+    /// The version control repository the Rhizfile is in, or else its
+    /// directory.
+    Repository,
+}
+
+/// Check that a destructive function can act on a path. Once it's resolved
+/// (see `files::resolve`), it mustn't be the Rhizfile's directory or contain
+/// it, and unless `allow_outside` it has to be `within` the project.
+fn check_inside(
+    fname: &str,
+    path: &Path,
+    within: Within,
+    allow_outside: bool,
+    ctx: &Context,
+) -> ExecutionResult {
+    let resolved = at_path(files::resolve(path), path)?;
+    let root = at_path(ctx.root.canonicalize(), &ctx.root)?;
+    if root.starts_with(&resolved) {
+        let msg = format!(
+            "`{}` won't act on the Rhizfile's directory or those containing it",
+            fname
+        );
+        error_with!(bad_path(&resolved, &msg));
+    }
+    let project = match within {
+        Within::RhizfileDir => &root,
+        Within::Repository => files::repository_root(&root).unwrap_or(&root),
+    };
+    if !allow_outside && !resolved.starts_with(project) {
+        let msg = format!(
+            "`{}` won't act on anything outside {} without `:allow-outside`",
+            fname,
+            project.display()
+        );
+        error_with!(bad_path(&resolved, &msg));
+    }
+    Ok(())
+}
+
+/// Delete a symlink itself, rather than what it points to.
+fn remove_symlink(path: &Path) -> ExecutionResult {
+    // On Windows, a link to a directory is removed like a directory.
+    let result = if cfg!(windows) && path.is_dir() {
+        fs::remove_dir(path)
+    } else {
+        fs::remove_file(path)
+    };
+    at_path(result, path)
+}
+
+fn describe_delete(fname: &str, args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let (targets, _) = delete_targets(fname, args, ctx)?;
+    targets.report(fname, ctx);
+    for target_path in &targets.paths {
+        ctx.print(&format!("{}: {}", fname, target_path.display()));
+    }
+    Ok(())
+}
+
+/// Resolve the sources and targets of a `copy` or `move`: the paths matching
+/// the source pattern, and where each one goes. If the target is a
+/// directory, the sources go into it with their own names; it has to be one
+/// if several sources match.
+fn transfer_paths(
+    fname: &str,
+    args: &[RhizValue],
+    ctx: &Context,
+) -> Result<(Targets, Vec<(PathBuf, PathBuf)>), ExecutionError> {
+    let working_dir = ctx.working_dir.as_path();
+    check_working_dir(ctx)?;
+    let mut spec = vec![("must-match", false)];
+    if fname == "move" {
+        spec.push(("allow-outside", false));
+    }
+    let (options, args) = FnOptions::take(fname, args, &spec)?;
+    check_args_len!(fname, args, 2);
+
+    let src = get_arg!(fname, args, 0, ctx);
+    let target = get_arg!(fname, args, 1, ctx);
+
+    let sources = Targets::find(fname, &src, options.flag("must-match"), ctx)?;
+    let target_arg = join_cwd(working_dir, &target);
+    let into_dir = target_arg.is_dir();
+    if sources.paths.len() > 1 && !into_dir {
+        let msg = format!(
+            "`{}`'s target should be a directory, since '{}' matches several files",
+            fname, src
+        );
+        error_with!(bad_path(&target_arg, &msg));
+    }
+    let mut pairs = Vec::new();
+    for src_path in &sources.paths {
+        let target_path = if into_dir {
+            match src_path.file_name() {
+                Some(name) => target_arg.join(name),
+                None => {
+                    let msg = format!("`{}` source doesn't have a file name", fname);
+                    error_with!(bad_path(src_path, &msg));
+                }
+            }
+        } else {
+            target_arg.clone()
+        };
+        if fname == "move" {
+            let allow_outside = options.flag("allow-outside");
+            check_inside(fname, src_path, Within::Repository, allow_outside, ctx)?;
+            check_inside(fname, &target_path, Within::Repository, allow_outside, ctx)?;
+        }
+        pairs.push((src_path.clone(), target_path));
+    }
+
+    Ok((sources, pairs))
+}
+
+/// Copy a file, or the files matching a glob pattern (won't overwrite an
+/// existing file).
+/// (copy [:must-match] source-or-pattern target)
+fn copy(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let (sources, pairs) = transfer_paths("copy", args, ctx)?;
+    sources.report("copy", ctx);
+
+    for (src_path, target_path) in &pairs {
+        if !(src_path.exists()) {
+            error_with!(bad_path(src_path, "`copy`'s source argument doesn't exist"));
+        }
+        if !(src_path.is_file()) {
+            error_with!(bad_path(src_path, "`copy` only acts on files"));
+        }
+        assert!(!target_path.is_dir());
+        if target_path.exists() {
+            error_with!(bad_path(
+                target_path,
+                "`copy` won't clobber an existing file"
+            ));
+        }
+
+        at_path(fs::copy(src_path, target_path), target_path)?;
+    }
 
     Ok(())
 }
 
+fn describe_copy(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    describe_transfer("copy", args, ctx)
+}
+
+fn describe_transfer(fname: &str, args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let (sources, pairs) = transfer_paths(fname, args, ctx)?;
+    sources.report(fname, ctx);
+    for (src_path, target_path) in &pairs {
+        ctx.print(&format!(
+            "{}: {} -> {}",
+            fname,
+            src_path.display(),
+            target_path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Move (rename) a file or directory, or those matching a glob pattern (won't
+/// overwrite anything).
+/// (move [:must-match] source-or-pattern target)
+fn move_paths(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let (sources, pairs) = transfer_paths("move", args, ctx)?;
+    sources.report("move", ctx);
+
+    for (src_path, target_path) in &pairs {
+        if !src_path.exists() {
+            error_with!(bad_path(src_path, "`move`'s source argument doesn't exist"));
+        }
+        if target_path.exists() {
+            error_with!(bad_path(
+                target_path,
+                "`move` won't clobber an existing file"
+            ));
+        }
+        match fs::rename(src_path, target_path) {
+            // A file can still be moved to another file system by copying it.
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices && src_path.is_file() => {
+                at_path(fs::copy(src_path, target_path), target_path)?;
+                at_path(fs::remove_file(src_path), src_path)?;
+            }
+            result => at_path(result, src_path)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn describe_move(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    describe_transfer("move", args, ctx)
+}
+
 mod copy_tools {
     use std::fs;
     use std::path::Path;
 
-    use crate::executor::{ExecutionError, ExecutionResult};
+    use super::{bad_path, is_symlink};
+    use crate::executor::{at_path, ExecutionError, ExecutionResult};
 
     fn copy_to(fpath: &Path, target_dir: &Path) -> ExecutionResult {
         assert!(fpath.exists() && fpath.is_file());
         assert!(target_dir.exists() && target_dir.is_dir());
 
         let mut target_dir_buf = target_dir.to_path_buf();
-        let target_fname = fpath
-            .file_name()
-            .ok_or_else(|| ExecutionError::from("File doesn't have a file name?"))?;
+        let target_fname = match fpath.file_name() {
+            Some(name) => name,
+            None => error_with!(bad_path(fpath, "File doesn't have a file name")),
+        };
         target_dir_buf.push(target_fname);
         let target_path = target_dir_buf.as_path();
 
-        fs::copy(fpath, target_path)?;
+        // Copying onto a symlink would write wherever it points.
+        if is_symlink(target_path) {
+            error_with!(bad_path(
+                target_path,
+                "`rec-copy` won't copy onto a symlink"
+            ));
+        }
+        at_path(fs::copy(fpath, target_path), target_path)?;
 
         Ok(())
     }
@@ -270,9 +1450,9 @@ mod copy_tools {
         assert!(dirpath.exists() && dirpath.is_dir());
         assert!(target_path.exists() && target_path.is_dir());
 
-        for entry_r in fs::read_dir(dirpath)? {
-            let entry = entry_r?;
-            let meta = entry.metadata()?;
+        for entry_r in at_path(fs::read_dir(dirpath), dirpath)? {
+            let entry = at_path(entry_r, dirpath)?;
+            let meta = at_path(entry.metadata(), &entry.path())?;
             if meta.is_file() {
                 copy_to(&entry.path(), target_path)?;
             } else if meta.is_dir() {
@@ -281,10 +1461,10 @@ mod copy_tools {
                     target_pathbuf.push(entry.file_name());
                     target_pathbuf
                 };
-                fs::create_dir(&target_dir_path)?;
+                at_path(fs::create_dir(&target_dir_path), &target_dir_path)?;
                 copy_dir(&entry.path(), &target_dir_path)?;
             } else {
-                error_with!("'{}' isn't a file or a directory?", entry.path().display());
+                error_with!(bad_path(&entry.path(), "Not a file or a directory"));
             }
         }
 
@@ -293,34 +1473,71 @@ mod copy_tools {
 }
 
 /// Recursively copy one directory's contents into another.
-fn rec_copy(args: &[RhizValue], working_dir: &Path) -> ExecutionResult {
-    assert!(working_dir.is_dir());
-    check_args_len!("rec-copy", args, 2);
-    let src = get_arg!("rec-copy", args, 0, RhizValue::String);
-    let target = get_arg!("rec-copy", args, 1, RhizValue::String);
+fn rec_copy(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let (src_path, target_path) = rec_copy_paths(args, ctx)?;
 
-    let src_path = working_dir.join(Path::new(src));
     if !src_path.exists() {
-        error_with!("source directory doesn't exist ({})", src_path.display());
+        error_with!(bad_path(&src_path, "source directory doesn't exist"));
     }
     if !src_path.is_dir() {
-        error_with!(
-            "source directory isn't a directory ({})",
-            src_path.display()
-        );
+        error_with!(bad_path(&src_path, "source directory isn't a directory"));
+    }
+    if is_symlink(&target_path) {
+        error_with!(bad_path(&target_path, "`rec-copy` won't follow a symlink"));
     }
-    let target_path = working_dir.join(Path::new(target));
     if !target_path.exists() {
-        error_with!("target directory doesn't exist ({})", target_path.display());
+        error_with!(bad_path(&target_path, "target directory doesn't exist"));
     }
     if !target_path.is_dir() {
-        error_with!(
-            "target directory isn't a directory ({})",
-            target_path.display()
-        );
+        error_with!(bad_path(&target_path, "target directory isn't a directory"));
     }
 
     copy_tools::copy_dir(&src_path, &target_path)?;
 
     Ok(())
 }
+
+fn rec_copy_paths(args: &[RhizValue], ctx: &Context) -> Result<(PathBuf, PathBuf), ExecutionError> {
+    let working_dir = ctx.working_dir.as_path();
+    check_working_dir(ctx)?;
+    let (options, args) = FnOptions::take("rec-copy", args, &[("allow-outside", false)])?;
+    check_args_len!("rec-copy", args, 2);
+    let src = get_arg!("rec-copy", args, 0, ctx);
+    let target = get_arg!("rec-copy", args, 1, ctx);
+
+    // The target's files can be overwritten.
+    let target_path = working_dir.join(Path::new(&target));
+    let allow_outside = options.flag("allow-outside");
+    check_inside(
+        "rec-copy",
+        &target_path,
+        Within::Repository,
+        allow_outside,
+        ctx,
+    )?;
+    Ok((working_dir.join(Path::new(&src)), target_path))
+}
+
+fn describe_rec_copy(args: &[RhizValue], ctx: &Context) -> ExecutionResult {
+    let (src_path, target_path) = rec_copy_paths(args, ctx)?;
+    ctx.print(&format!(
+        "rec-copy: {} -> {}",
+        src_path.display(),
+        target_path.display()
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_quote_word() {
+    let cases = [
+        ("cargo", "cargo"),
+        ("--target-dir=./target", "--target-dir=./target"),
+        ("hello world", "'hello world'"),
+        ("", "''"),
+        ("it's", "'it'\\''s'"),
+    ];
+    for (word, expected) in &cases {
+        assert_eq!(&quote_word(word), expected);
+    }
+}
@@ -0,0 +1,98 @@
+//! Job slots, which limit how many external commands run at once.
+//!
+//! Each run has a set of slots (`-j N`), and a `(par :jobs N ...)` adds a
+//! nested set for its branches. A command takes a slot from its innermost set
+//! and from each one enclosing it, so it counts against all of their limits.
+use std::sync::{Arc, Condvar, Mutex};
+
+pub struct JobSlots {
+    limit: usize,
+    running: Mutex<usize>,
+    freed: Condvar,
+    parent: Option<Arc<JobSlots>>,
+}
+
+impl JobSlots {
+    fn with_parent(limit: usize, parent: Option<Arc<JobSlots>>) -> Arc<JobSlots> {
+        Arc::new(JobSlots {
+            limit: limit.max(1),
+            running: Mutex::new(0),
+            freed: Condvar::new(),
+            parent,
+        })
+    }
+
+    /// Slots for at most `limit` commands (and at least one).
+    pub fn new(limit: usize) -> Arc<JobSlots> {
+        JobSlots::with_parent(limit, None)
+    }
+
+    /// Slots for at most `limit` commands, which also count against
+    /// `parent`'s limit.
+    pub fn nested(limit: usize, parent: &Arc<JobSlots>) -> Arc<JobSlots> {
+        JobSlots::with_parent(limit, Some(Arc::clone(parent)))
+    }
+
+    /// One slot for each CPU.
+    pub fn per_cpu() -> Arc<JobSlots> {
+        JobSlots::new(std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// Wait for a free slot here and in each enclosing set of slots. The
+    /// slots are freed when the returned `JobSlot` is dropped.
+    pub fn acquire(self: &Arc<Self>) -> JobSlot {
+        let mut held = Vec::new();
+        let mut slots = Some(self);
+        while let Some(s) = slots {
+            let mut running = s.running.lock().unwrap();
+            while *running >= s.limit {
+                running = s.freed.wait(running).unwrap();
+            }
+            *running += 1;
+            held.push(Arc::clone(s));
+            slots = s.parent.as_ref();
+        }
+        JobSlot { held }
+    }
+}
+
+/// The slots taken for a running command.
+pub struct JobSlot {
+    held: Vec<Arc<JobSlots>>,
+}
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        for slots in &self.held {
+            *slots.running.lock().unwrap() -= 1;
+            slots.freed.notify_one();
+        }
+    }
+}
+
+#[test]
+fn test_job_slots() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    let outer = JobSlots::new(3);
+    let inner = JobSlots::nested(2, &outer);
+    let running = AtomicUsize::new(0);
+    let max_running = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..6 {
+            scope.spawn(|| {
+                let _slot = inner.acquire();
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        // The inner slots count against the outer ones.
+        let _slot = outer.acquire();
+    });
+    assert!(max_running.load(Ordering::SeqCst) <= 2);
+    assert_eq!(*outer.running.lock().unwrap(), 0);
+}
@@ -22,7 +22,15 @@
 extern crate pest_derive;
 
 pub mod ast;
+pub mod cache;
 pub mod compiler;
+mod conditions;
 pub mod executor;
+mod files;
 mod functions;
+mod jobs;
+mod output;
 mod parser;
+mod timeout;
+mod variables;
+pub mod watch;
@@ -0,0 +1,204 @@
+//! Where the output of commands goes, so that parallel branches' output can
+//! be labelled or kept together instead of interleaving.
+use std::io::{self, Read, Write};
+use std::process::{ChildStderr, ChildStdout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// A stream of a command's output, which can be waited on without reading it.
+pub trait Pipe: Read + Send {
+    /// Wait up to `timeout` for there to be something to read (or for the
+    /// stream to close). Streams which can't be waited on are always ready.
+    fn wait_ready(&self, _timeout: Duration) -> bool {
+        true
+    }
+}
+
+#[cfg(unix)]
+fn wait_ready(fd: std::os::unix::io::RawFd, timeout: Duration) -> bool {
+    let mut poll_fd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // An error counts as ready, so that reading reports it.
+    unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) != 0 }
+}
+
+impl Pipe for ChildStdout {
+    #[cfg(unix)]
+    fn wait_ready(&self, timeout: Duration) -> bool {
+        wait_ready(std::os::unix::io::AsRawFd::as_raw_fd(self), timeout)
+    }
+}
+
+impl Pipe for ChildStderr {
+    #[cfg(unix)]
+    fn wait_ready(&self, timeout: Duration) -> bool {
+        wait_ready(std::os::unix::io::AsRawFd::as_raw_fd(self), timeout)
+    }
+}
+
+impl Pipe for &[u8] {}
+
+/// How often forwarding checks whether the command has exited.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The destination of a branch's output. Labelled and buffered outputs pass
+/// their lines on to the output of the branch they're in.
+pub enum Output {
+    /// Straight to the terminal (external commands inherit it).
+    Terminal,
+    /// Line by line, each prefixed with `[label]`.
+    Prefixed { label: String, parent: Arc<Output> },
+    /// Into a buffer, which is passed on under a `[label]` header by
+    /// `finish`.
+    Block {
+        label: String,
+        lines: Mutex<Vec<(Stream, String)>>,
+        parent: Arc<Output>,
+    },
+}
+
+impl Output {
+    pub fn prefixed(label: &str, parent: &Arc<Output>) -> Arc<Output> {
+        Arc::new(Output::Prefixed {
+            label: label.to_owned(),
+            parent: Arc::clone(parent),
+        })
+    }
+
+    pub fn block(label: &str, parent: &Arc<Output>) -> Arc<Output> {
+        Arc::new(Output::Block {
+            label: label.to_owned(),
+            lines: Mutex::new(Vec::new()),
+            parent: Arc::clone(parent),
+        })
+    }
+
+    /// Whether external commands' output has to be captured and passed to
+    /// `print`, rather than going straight to the terminal.
+    pub fn captures(&self) -> bool {
+        !matches!(self, Output::Terminal)
+    }
+
+    pub fn print(&self, stream: Stream, line: &str) {
+        match self {
+            Output::Terminal => {
+                // Each line is written under the stream's lock, so lines
+                // from different threads don't run together.
+                let _ = match stream {
+                    Stream::Stdout => writeln!(io::stdout().lock(), "{}", line),
+                    Stream::Stderr => writeln!(io::stderr().lock(), "{}", line),
+                };
+            }
+            Output::Prefixed { label, parent } => {
+                parent.print(stream, &format!("[{}] {}", label, line))
+            }
+            Output::Block { lines, .. } => {
+                lines.lock().unwrap().push((stream, line.to_owned()));
+            }
+        }
+    }
+
+    /// Pass a block's buffered lines on, if there are any.
+    pub fn finish(&self) {
+        if let Output::Block {
+            label,
+            lines,
+            parent,
+        } = self
+        {
+            let lines = std::mem::take(&mut *lines.lock().unwrap());
+            if lines.is_empty() {
+                return;
+            }
+            parent.print(Stream::Stdout, &format!("[{}]", label));
+            for (stream, line) in lines {
+                parent.print(stream, &line);
+            }
+        }
+    }
+
+    /// Print the lines read from a command's stdout and stderr (those that
+    /// aren't redirected elsewhere) until they're both closed, or until
+    /// `exited` is set and there's nothing left to read: a process the
+    /// command left running in the background could keep them open
+    /// indefinitely.
+    pub fn forward<O, E>(&self, stdout: Option<O>, stderr: Option<E>, exited: &AtomicBool)
+    where
+        O: Pipe,
+        E: Pipe,
+    {
+        thread::scope(|scope| {
+            if let Some(stdout) = stdout {
+                scope.spawn(|| self.forward_lines(Stream::Stdout, stdout, exited));
+            }
+            if let Some(stderr) = stderr {
+                self.forward_lines(Stream::Stderr, stderr, exited);
+            }
+        });
+    }
+
+    fn forward_lines<P: Pipe>(&self, stream: Stream, mut pipe: P, exited: &AtomicBool) {
+        let mut pending = Vec::new();
+        let mut buffer = [0; 8192];
+        loop {
+            if !pipe.wait_ready(EXIT_POLL_INTERVAL) {
+                if exited.load(Ordering::SeqCst) {
+                    break;
+                }
+                continue;
+            }
+            let n = match pipe.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            pending.extend_from_slice(&buffer[..n]);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                self.print_bytes(stream, &line);
+            }
+        }
+        if !pending.is_empty() {
+            self.print_bytes(stream, &pending);
+        }
+    }
+
+    fn print_bytes(&self, stream: Stream, line: &[u8]) {
+        let text = String::from_utf8_lossy(line);
+        self.print(stream, text.trim_end_matches(['\n', '\r']));
+    }
+}
+
+#[test]
+fn test_block_output() {
+    let terminal = Arc::new(Output::Terminal);
+    let outer = Output::block("outer", &terminal);
+    let inner = Output::prefixed("1", &outer);
+    let exited = AtomicBool::new(false);
+    inner.forward(Some(&b"one\ntwo"[..]), Some(&b"three\n"[..]), &exited);
+    match &*outer {
+        Output::Block { lines, .. } => {
+            let mut lines: Vec<String> = lines
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, l)| l.clone())
+                .collect();
+            lines.sort();
+            assert_eq!(lines, vec!["[1] one", "[1] three", "[1] two"]);
+        }
+        _ => unreachable!(),
+    }
+}
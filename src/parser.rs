@@ -14,7 +14,16 @@ fn test_parse_empty_sexpr() {
 
 #[test]
 fn test_parse_symbol() {
-    let ok_cases = ["asdf", "JklL)", "x1 ", "kebab-case\n", "-l"];
+    let ok_cases = [
+        "asdf",
+        "JklL)",
+        "x1 ",
+        "kebab-case\n",
+        "-l",
+        "${dist}/app",
+        ":env",
+        "FOO=bar",
+    ];
     for case in &ok_cases {
         let msg = format!("Expected '{}' to parse to a symbol", case);
         RhizParser::parse(Rule::symbol, case).expect(&msg);
@@ -0,0 +1,197 @@
+//! Time limits for external commands and tasks.
+use std::io;
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a command with a time limit is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Parse a duration like `500ms`, `30s`, `5m` or `1h`; a plain number is
+/// a number of seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let seconds_per_unit = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 60.0 * 60.0,
+        _ => return Err(format!("Invalid duration: '{}' (e.g. 30s, 5m)", text)),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n > 0.0 => Ok(Duration::from_secs_f64(n * seconds_per_unit)),
+        _ => Err(format!("Invalid duration: '{}' (e.g. 30s, 5m)", text)),
+    }
+}
+
+/// When something has to be finished by.
+#[derive(Clone)]
+pub struct Deadline {
+    pub at: Instant,
+    /// The time limit, for error messages.
+    pub limit: Duration,
+    /// What the limit is for, e.g. "Task 'test'".
+    pub what: String,
+}
+
+impl Deadline {
+    pub fn new(limit: Duration, what: String) -> Deadline {
+        Deadline {
+            at: Instant::now() + limit,
+            limit,
+            what,
+        }
+    }
+}
+
+/// Keeps track of the process groups commands are started in, so that a
+/// SIGINT or SIGTERM for rhiz reaches them too: they aren't in the terminal's
+/// foreground process group, so they don't get its Ctrl-C.
+#[cfg(unix)]
+mod groups {
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Once;
+
+    const MAX_GROUPS: usize = 256;
+    /// Zero marks a free slot, and -1 one that's been taken for a command
+    /// that's being started.
+    static GROUPS: [AtomicI32; MAX_GROUPS] = [const { AtomicI32::new(0) }; MAX_GROUPS];
+    static INSTALL_HANDLERS: Once = Once::new();
+
+    /// Pass a signal on to the groups, then let it do what it would have done
+    /// to rhiz. (Only async-signal-safe calls are made.)
+    extern "C" fn forward(signal: libc::c_int) {
+        for group in &GROUPS {
+            let id = group.load(Ordering::SeqCst);
+            if id > 0 {
+                unsafe { libc::kill(-id, signal) };
+            }
+        }
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    fn install_handlers() {
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            let handler = forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            // A signal rhiz was told to ignore stays ignored.
+            if unsafe { libc::signal(signal, handler) } == libc::SIG_IGN {
+                unsafe { libc::signal(signal, libc::SIG_IGN) };
+            }
+        }
+    }
+
+    /// Take a slot for a new group, if there's one free.
+    pub fn reserve() -> Option<usize> {
+        INSTALL_HANDLERS.call_once(install_handlers);
+        GROUPS.iter().position(|group| {
+            group
+                .compare_exchange(0, -1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        })
+    }
+
+    pub fn set(slot: usize, id: u32) {
+        GROUPS[slot].store(id as i32, Ordering::SeqCst);
+    }
+
+    pub fn release(slot: usize) {
+        GROUPS[slot].store(0, Ordering::SeqCst);
+    }
+
+    pub fn remove(id: u32) {
+        for group in &GROUPS {
+            let _ = group.compare_exchange(id as i32, 0, Ordering::SeqCst, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Start a command in its own process group (on Unix), so that it can be
+/// killed along with any processes it starts. rhiz passes on a SIGINT or
+/// SIGTERM to the group until `wait_with_deadline` returns; if there are too
+/// many groups to keep track of, the command stays in rhiz's group instead.
+pub fn spawn_in_group(command: &mut Command) -> io::Result<Child> {
+    #[cfg(unix)]
+    {
+        let slot = match groups::reserve() {
+            Some(slot) => slot,
+            None => return command.spawn(),
+        };
+        std::os::unix::process::CommandExt::process_group(command, 0);
+        match command.spawn() {
+            Ok(child) => {
+                groups::set(slot, child.id());
+                Ok(child)
+            }
+            Err(e) => {
+                groups::release(slot);
+                Err(e)
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    command.spawn()
+}
+
+fn kill_group(child: &mut Child) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        // A child started in its own process group leads it, so the group's
+        // ID is the child's; for one that wasn't, this fails.
+        if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } == 0 {
+            return Ok(());
+        }
+    }
+    child.kill()
+}
+
+/// Wait for a command to exit, killing it (and its process group, if it was
+/// started with `spawn_in_group`) if it's still running at the deadline.
+/// Gives `None` if it was killed.
+pub fn wait_with_deadline(
+    child: &mut Child,
+    deadline: &Deadline,
+) -> io::Result<Option<ExitStatus>> {
+    let result = wait_or_kill(child, deadline);
+    #[cfg(unix)]
+    groups::remove(child.id());
+    result
+}
+
+fn wait_or_kill(child: &mut Child, deadline: &Deadline) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline.at {
+            kill_group(child)?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline.at - now));
+    }
+}
+
+#[test]
+fn test_parse_duration() {
+    let cases = [
+        ("500ms", Duration::from_millis(500)),
+        ("30s", Duration::from_secs(30)),
+        ("1.5s", Duration::from_millis(1500)),
+        ("5m", Duration::from_secs(300)),
+        ("1h", Duration::from_secs(3600)),
+        ("10", Duration::from_secs(10)),
+    ];
+    for (text, expected) in &cases {
+        assert_eq!(parse_duration(text), Ok(*expected), "{}", text);
+    }
+    for text in &["", "s", "0s", "-1s", "5 m", "5d", "1.2.3s"] {
+        assert!(parse_duration(text).is_err(), "{}", text);
+    }
+}
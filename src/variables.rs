@@ -0,0 +1,170 @@
+//! Substitution of `${name}` references in the strings of a Rhizfile, and
+//! parsing of environment variable settings.
+//!
+//! `${env:NAME}` refers to the environment variable `NAME`, and
+//! `${name:-default}` gives `default` if `name` is unset or empty. `$$` is a
+//! literal `$`, and a `$` that isn't followed by `{` or `$` is left as it is.
+use crate::ast::RhizValue;
+
+/// Prefix of references to environment variables.
+pub const ENV_PREFIX: &str = "env:";
+
+/// Replace each `${name}` in `text` with the value `lookup` gives for `name`.
+pub fn substitute<F>(text: &str, lookup: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find('$') {
+        result.push_str(&rest[..idx]);
+        let after = &rest[idx + 1..];
+        if let Some(after_dollar) = after.strip_prefix('$') {
+            result.push('$');
+            rest = after_dollar;
+        } else if let Some(reference) = after.strip_prefix('{') {
+            let end = match reference.find('}') {
+                Some(end) => end,
+                None => return Err(format!("Unclosed variable reference in \"{}\"", text)),
+            };
+            let (name, default) = match reference[..end].find(":-") {
+                Some(i) => (&reference[..i], Some(&reference[i + 2..end])),
+                None => (&reference[..end], None),
+            };
+            if !is_valid_name(name.strip_prefix(ENV_PREFIX).unwrap_or(name)) {
+                return Err(format!("Invalid variable name: '{}'", name));
+            }
+            match (lookup(name), default) {
+                (Some(value), Some(default)) if value.is_empty() => result.push_str(default),
+                (Some(value), _) => result.push_str(&value),
+                (None, Some(default)) => result.push_str(default),
+                (None, None) => return Err(format!("Undefined variable: '{}'", name)),
+            }
+            rest = &reference[end + 1..];
+        } else {
+            result.push('$');
+            rest = after;
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Variable names are made of letters, digits, `-`, `_` and `.`.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// A change to the environment of external commands.
+pub struct EnvSetting<'v> {
+    pub name: &'v str,
+    /// The (unsubstituted) new value, or `None` to unset the variable.
+    pub value: Option<&'v str>,
+    pub source: &'v RhizValue,
+}
+
+/// Parse a list of `NAME=value` and `:unset NAME` environment settings.
+pub fn parse_env_settings<'v>(
+    items: &[&'v RhizValue],
+) -> Result<Vec<EnvSetting<'v>>, (String, &'v RhizValue)> {
+    let mut settings = Vec::new();
+    let mut items = items.iter();
+    while let Some(item) = items.next() {
+        let text = match item {
+            RhizValue::String(s, _) => s,
+            _ => return Err(("Expected an environment setting".to_owned(), item)),
+        };
+        if text == ":unset" {
+            match items.next() {
+                Some(RhizValue::String(name, _)) => settings.push(EnvSetting {
+                    name,
+                    value: None,
+                    source: item,
+                }),
+                _ => return Err(("`:unset` needs a variable name".to_owned(), item)),
+            }
+            continue;
+        }
+        match text.find('=') {
+            Some(i) if i > 0 => settings.push(EnvSetting {
+                name: &text[..i],
+                value: Some(&text[i + 1..]),
+                source: item,
+            }),
+            _ => {
+                let msg = "Environment settings should be of the form NAME=value";
+                return Err((msg.to_owned(), item));
+            }
+        }
+    }
+    Ok(settings)
+}
+
+/// Look up a process environment variable for an `env:NAME` reference.
+pub fn lookup_env(reference: &str) -> Option<String> {
+    let name = reference.strip_prefix(ENV_PREFIX)?;
+    std::env::var(name).ok()
+}
+
+#[test]
+fn test_substitute() {
+    let lookup = |name: &str| match name {
+        "dist" => Some("./target/dist".to_owned()),
+        "app" => Some("rhiz".to_owned()),
+        "empty" => Some(String::new()),
+        "env:EDITOR" => Some("vi".to_owned()),
+        _ => None,
+    };
+    let ok_cases = [
+        ("plain", "plain"),
+        ("${dist}", "./target/dist"),
+        ("${dist}/${app}.tar", "./target/dist/rhiz.tar"),
+        ("$$HOME", "$HOME"),
+        ("cost: $5", "cost: $5"),
+        ("$$${app}", "$rhiz"),
+        ("${env:EDITOR}", "vi"),
+        ("${env:SHELL:-sh}", "sh"),
+        ("${app:-other}", "rhiz"),
+        ("${empty:-other}", "other"),
+        ("${missing:-}", ""),
+    ];
+    for (text, expected) in &ok_cases {
+        assert_eq!(&substitute(text, lookup).unwrap(), expected);
+    }
+    let err_cases = ["${missing}", "${dist", "${}", "${a b}", "${env:}"];
+    for text in &err_cases {
+        assert!(
+            substitute(text, lookup).is_err(),
+            "Expected '{}' not to substitute",
+            text
+        );
+    }
+}
+
+#[test]
+fn test_parse_env_settings() {
+    let prog =
+        crate::ast::parse_rhiz_program(r#"(FOO=bar "GREETING=hello world" :unset HOME)"#).unwrap();
+    let items: Vec<&RhizValue> = match &prog {
+        RhizValue::Program(sexprs, _) => match &sexprs[0] {
+            RhizValue::SExpr(items, _) => items.iter().collect(),
+            _ => panic!("Expected a sexpr"),
+        },
+        _ => panic!("Expected a program"),
+    };
+    let settings = parse_env_settings(&items).unwrap();
+    let parsed: Vec<(&str, Option<&str>)> = settings.iter().map(|s| (s.name, s.value)).collect();
+    assert_eq!(
+        parsed,
+        vec![
+            ("FOO", Some("bar")),
+            ("GREETING", Some("hello world")),
+            ("HOME", None)
+        ]
+    );
+    assert!(parse_env_settings(&items[..1]).is_ok());
+    assert!(parse_env_settings(&items[2..3]).is_err());
+}
@@ -0,0 +1,122 @@
+//! Polling for changes to the files matching some glob patterns.
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::files;
+
+/// How often the files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the files must go without changing before a change is reported,
+/// so that a burst of saves is reported once.
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(300);
+
+type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Watches the files matching some glob patterns (relative to a directory).
+pub struct Watcher {
+    dir: PathBuf,
+    patterns: Vec<String>,
+    snapshot: Snapshot,
+}
+
+impl Watcher {
+    pub fn new(dir: &Path, patterns: Vec<String>) -> io::Result<Watcher> {
+        let mut watcher = Watcher {
+            dir: dir.to_path_buf(),
+            patterns,
+            snapshot: Snapshot::new(),
+        };
+        watcher.snapshot = watcher.take_snapshot()?;
+        Ok(watcher)
+    }
+
+    /// The modification time and size of each matching file. Files which
+    /// disappear while the snapshot is taken are left out.
+    fn take_snapshot(&self) -> io::Result<Snapshot> {
+        let mut snapshot = Snapshot::new();
+        for pattern in &self.patterns {
+            let paths = files::expand_pattern(&self.dir, pattern)
+                .map_err(|msg| io::Error::new(io::ErrorKind::InvalidInput, msg))?;
+            for path in paths {
+                if let Ok(meta) = path.metadata() {
+                    snapshot.insert(path, (meta.modified().ok(), meta.len()));
+                }
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Check whether any file has been added, removed or changed since the
+    /// last check.
+    pub fn poll(&mut self) -> io::Result<bool> {
+        let snapshot = self.take_snapshot()?;
+        let changed = snapshot != self.snapshot;
+        self.snapshot = snapshot;
+        Ok(changed)
+    }
+
+    /// Block until the files change and then stop changing. Changes made
+    /// since the last check (e.g. while the tasks were running) count too.
+    pub fn wait_for_change(&mut self) -> io::Result<()> {
+        while !self.poll()? {
+            thread::sleep(POLL_INTERVAL);
+        }
+        loop {
+            thread::sleep(DEBOUNCE_INTERVAL);
+            if !self.poll()? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "glob")]
+#[test]
+fn test_poll() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join("rhiz-test-watch-poll");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("watched"), "contents").unwrap();
+    fs::write(dir.join("ignored"), "contents").unwrap();
+
+    let mut watcher = Watcher::new(&dir, vec!["watched*".to_owned()]).unwrap();
+    assert!(!watcher.poll().unwrap());
+
+    fs::write(dir.join("ignored"), "new contents").unwrap();
+    assert!(!watcher.poll().unwrap());
+
+    fs::write(dir.join("watched"), "new contents").unwrap();
+    assert!(watcher.poll().unwrap());
+    assert!(!watcher.poll().unwrap());
+
+    fs::write(dir.join("watched-too"), "contents").unwrap();
+    assert!(watcher.poll().unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "glob")]
+#[test]
+fn test_change_during_run() {
+    use std::fs;
+    use std::sync::mpsc;
+
+    let dir = std::env::temp_dir().join("rhiz-test-watch-during-run");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("watched"), "contents").unwrap();
+
+    // The watcher is made before the tasks run, and a source is edited while
+    // they do.
+    let mut watcher = Watcher::new(&dir, vec!["watched".to_owned()]).unwrap();
+    fs::write(dir.join("watched"), "new contents").unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(watcher.wait_for_change().is_ok()));
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(true));
+    fs::remove_dir_all(&dir).unwrap();
+}
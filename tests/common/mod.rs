@@ -0,0 +1,105 @@
+//! Set-up shared by the integration tests.
+// Each test crate only uses some of the helpers.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use rhiz::ast::parse_rhiz_program;
+use rhiz::compiler::compile;
+use rhiz::executor::{self, ExecutionResult, Invocation, Options};
+
+/// A Rhizfile in a fresh directory under the system's temporary directory.
+/// The directory is deleted when the test passes (and kept if it fails).
+pub struct TestProject {
+    /// The directory holding the project, and anything outside it.
+    pub root: PathBuf,
+    /// The project's directory, where the Rhizfile is and its tasks run.
+    pub dir: PathBuf,
+    src: String,
+}
+
+impl TestProject {
+    /// Make `<temp>/<name>/project` (replacing anything left by an earlier
+    /// run), with a Rhizfile containing `src`.
+    pub fn new(name: &str, src: &str) -> TestProject {
+        let root = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("project");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Rhizfile"), src).unwrap();
+        TestProject {
+            root,
+            dir,
+            src: src.to_owned(),
+        }
+    }
+
+    /// A path relative to the project's directory.
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    /// Make some directories (relative to the project's directory).
+    pub fn create_dirs(&self, paths: &[&str]) {
+        for path in paths {
+            fs::create_dir_all(self.path(path)).unwrap();
+        }
+    }
+
+    /// Write a file (relative to the project's directory), making its
+    /// directory if need be.
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.path(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.path(path)).unwrap()
+    }
+
+    pub fn exec(&self, task: &str) -> ExecutionResult {
+        let parsed = parse_rhiz_program(&self.src).unwrap();
+        let compiled = compile(&parsed).unwrap();
+        executor::exec_task(task, &compiled, &self.dir)
+    }
+
+    pub fn exec_with_args(&self, task: &str, args: &[&str]) -> ExecutionResult {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let parsed = parse_rhiz_program(&self.src).unwrap();
+        let compiled = compile(&parsed).unwrap();
+        executor::exec_task_with_args(task, &args, &compiled, &self.dir)
+    }
+
+    pub fn exec_tasks(&self, tasks: &[&str], options: &Options) -> ExecutionResult {
+        let invocations: Vec<Invocation> = tasks
+            .iter()
+            .map(|task| Invocation {
+                task: task.to_string(),
+                args: Vec::new(),
+            })
+            .collect();
+        let parsed = parse_rhiz_program(&self.src).unwrap();
+        let compiled = compile(&parsed).unwrap();
+        executor::exec_tasks(&invocations, &compiled, &self.dir, options)
+    }
+
+    /// Run the `rhiz` binary in the project's directory.
+    pub fn rhiz(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_rhiz"))
+            .args(args)
+            .current_dir(&self.dir)
+            .output()
+            .unwrap()
+    }
+}
+
+impl Drop for TestProject {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+}
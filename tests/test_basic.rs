@@ -1,3 +1,5 @@
+#![allow(clippy::needless_borrow)]
+
 use rhiz::ast::parse_rhiz_program;
 use rhiz::compiler::compile;
 use rhiz::executor::exec_task;
@@ -0,0 +1,31 @@
+mod common;
+
+use common::TestProject;
+
+#[test]
+fn test_conditionals() {
+    let project = TestProject::new(
+        "rhiz-test-conditions",
+        r#"
+        (task "branch"
+          (params mode)
+          (env RHIZ_TEST_SET=1 :unset RHIZ_TEST_UNSET)
+          (if (equal ${mode} "release") (empty-dir "release") (empty-dir "debug"))
+          (when (exists "release") (empty-dir "release/when"))
+          (unless (exists "release") (empty-dir "debug/unless"))
+          (when (and (env-set RHIZ_TEST_SET) (not (env-set RHIZ_TEST_UNSET)))
+            (empty-dir "${mode}/env"))
+          (if (or (os "unix") (os "windows")) (empty-dir "${mode}/os")))
+        "#,
+    );
+
+    project.exec_with_args("branch", &["debug"]).unwrap();
+    assert!(!project.path("release").exists());
+    assert!(project.path("debug/unless").exists());
+    assert!(project.path("debug/env").exists());
+    assert!(project.path("debug/os").exists());
+
+    project.exec_with_args("branch", &["release"]).unwrap();
+    assert!(project.path("release/when").exists());
+    assert!(project.path("release/env").exists());
+}
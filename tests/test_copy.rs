@@ -0,0 +1,18 @@
+mod common;
+
+use common::TestProject;
+
+#[test]
+fn test_copy_relative_to_working_dir() {
+    // The paths are relative to the Rhizfile's directory, not to wherever the
+    // tests are run from.
+    let project = TestProject::new(
+        "rhiz-test-copy",
+        r#"(task "copy" (copy "source" "copied"))"#,
+    );
+    project.write("source", "test contents");
+
+    project.exec("copy").unwrap();
+    assert_eq!(project.read("copied"), "test contents");
+    assert_eq!(project.read("source"), "test contents");
+}
@@ -1,6 +1,11 @@
+#![allow(clippy::needless_borrows_for_generic_args)]
+
+mod common;
+
+use common::TestProject;
 use rhiz::ast::parse_rhiz_program;
 use rhiz::compiler::compile;
-use rhiz::executor::exec_task;
+use rhiz::executor::{exec_task, ErrorKind};
 
 #[test]
 fn test_delete_file() {
@@ -26,3 +31,46 @@ fn test_delete_file() {
 
     assert!(!fpath.exists());
 }
+
+#[test]
+fn test_delete_dir() {
+    let project = TestProject::new(
+        "rhiz-test-delete-dir",
+        r#"
+        (task "target" (delete-dir "target") (delete-dir "missing"))
+        (task "modules" (delete :recursive "*/node_modules"))
+        (task "file" (delete-dir "file.txt"))
+        (task "root" (delete-dir "web/.."))
+        (task "outside" (delete-dir "../outside"))
+        (task "allowed" (delete-dir :allow-outside "../outside"))
+        "#,
+    );
+    let outside = project.root.join("outside");
+    project.create_dirs(&["web/node_modules/a", "api/node_modules"]);
+    std::fs::create_dir_all(&outside).unwrap();
+    project.write("target/debug/app", "app");
+    project.write("file.txt", "file");
+
+    project.exec("target").unwrap();
+    assert!(!project.path("target").exists());
+
+    project.exec("modules").unwrap();
+    assert!(!project.path("web/node_modules").exists());
+    assert!(!project.path("api/node_modules").exists());
+    assert!(project.path("web").is_dir());
+
+    let err = project.exec("file").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { .. }));
+    assert!(project.path("file.txt").exists());
+
+    let err = project.exec("root").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { .. }));
+    assert!(project.dir.is_dir());
+
+    let err = project.exec("outside").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { .. }));
+    assert!(outside.is_dir());
+
+    project.exec("allowed").unwrap();
+    assert!(!outside.exists());
+}
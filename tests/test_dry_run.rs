@@ -0,0 +1,56 @@
+mod common;
+
+use common::TestProject;
+use rhiz::executor::Options;
+
+#[test]
+fn test_dry_run_changes_nothing() {
+    let project = TestProject::new(
+        "rhiz-test-dry-run",
+        r#"
+        (task "destroy"
+          (empty-dir "full")
+          (delete "source")
+          (copy "missing" "copied")
+          (rec-copy "full" "elsewhere")
+          (exec rhiz-test-command-which-does-not-exist))
+        "#,
+    );
+    project.write("full/file", "test contents");
+    project.write("source", "test contents");
+
+    let options = Options {
+        dry_run: true,
+        ..Options::default()
+    };
+    project.exec_tasks(&["destroy"], &options).unwrap();
+
+    assert!(project.path("full/file").exists());
+    assert!(project.path("source").exists());
+}
+
+#[test]
+fn test_dry_run_describes_actions() {
+    let project = TestProject::new(
+        "rhiz-test-dry-run-describe",
+        r#"
+        (task "deploy"
+          (params target)
+          (empty-dir "out")
+          (copy "source" "out/copied")
+          (exec ./deploy.sh ${target} "two words"))
+        "#,
+    );
+    project.write("source", "test contents");
+
+    // Options can follow the task, rather than being taken as its values.
+    let output = project.rhiz(&["deploy", "--dry-run", "prod"]);
+    assert!(output.status.success());
+    let dir = project.dir.canonicalize().unwrap();
+    let expected = format!(
+        "empty-dir: {dir}/out\ncopy: {dir}/source -> {dir}/out/copied\nexec: ./deploy.sh prod 'two words'\n",
+        dir = dir.display()
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    assert!(!project.path("out").exists());
+}
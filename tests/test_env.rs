@@ -0,0 +1,32 @@
+mod common;
+
+use common::TestProject;
+
+#[cfg(unix)]
+#[test]
+fn test_env_settings() {
+    let project = TestProject::new(
+        "rhiz-test-env",
+        r#"
+        (task "task-env"
+          (env GREETING=hello "TARGET=the world")
+          (exec sh -c "test $GREETING = hello")
+          (exec test "${env:TARGET}" = "the world"))
+        (task "exec-env"
+          (env GREETING=hello)
+          (exec :env GREETING=goodbye sh -c "test $GREETING = goodbye")
+          (exec :unset GREETING sh -c "test -z $GREETING"))
+        (task "read-env"
+          (env GREETING=hello)
+          (exec test "${env:GREETING}" = hello)
+          (exec test "${env:RHIZ_TEST_UNSET_VARIABLE:-fallback}" = fallback))
+        (task "missing-env"
+          (exec test "${env:RHIZ_TEST_UNSET_VARIABLE}" = ""))
+        "#,
+    );
+
+    project.exec("task-env").unwrap();
+    project.exec("exec-env").unwrap();
+    project.exec("read-env").unwrap();
+    assert!(project.exec("missing-env").is_err());
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::TestProject;
+use rhiz::executor::ErrorKind;
+
+#[test]
+fn test_error_handling_forms() {
+    let project = TestProject::new(
+        "rhiz-test-error-handling",
+        r#"
+        (task "ignore"
+          (ignore-errors (copy "missing" "a") (empty-dir "ignored"))
+          (empty-dir "after-ignore"))
+        (task "catch"
+          (try
+            (copy "missing" "a")
+            (empty-dir "not-reached")
+            (catch (empty-dir "caught") (log "Caught: ${error}"))
+            (finally (empty-dir "finally"))))
+        (task "rethrow"
+          (try
+            (copy "missing" "a")
+            (finally (copy "missing-too" "b") (empty-dir "not-reached"))))
+        (task "task-finally"
+          (copy "missing" "a")
+          (finally (empty-dir "task-finally")))
+        "#,
+    );
+
+    project.exec("ignore").unwrap();
+    assert!(project.path("ignored").exists());
+    assert!(project.path("after-ignore").exists());
+
+    project.exec("catch").unwrap();
+    assert!(project.path("caught").exists());
+    assert!(project.path("finally").exists());
+    assert!(!project.path("not-reached").exists());
+
+    // Both the error and the cleanup's error are reported.
+    let err = project.exec("rethrow").unwrap_err();
+    match err.kind() {
+        ErrorKind::Multiple(errors) => {
+            assert_eq!(errors.len(), 2);
+            assert!(errors[0].to_string().contains("source argument"));
+            assert!(errors[1].to_string().contains("missing-too"));
+        }
+        kind => panic!("Unexpected error: {}", kind),
+    }
+    assert!(!project.path("not-reached").exists());
+
+    assert!(project.exec("task-finally").is_err());
+    assert!(project.path("task-finally").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_retry() {
+    // Each attempt adds a line to `attempts`, and succeeds from the third.
+    let project = TestProject::new(
+        "rhiz-test-retry",
+        r#"
+        (task "flaky"
+          (retry 3 10ms
+            (exec sh -c "echo x >> attempts; test $$(wc -l < attempts) -ge 3")))
+        (task "failing"
+          (retry 2 (copy "missing" "a")))
+        (task "out-of-time"
+          (timeout 200ms)
+          (retry 3 10s (copy "missing" "a")))
+        "#,
+    );
+
+    project.exec("flaky").unwrap();
+    assert_eq!(project.read("attempts").lines().count(), 3);
+
+    // After the last attempt, its error is reported.
+    let err = project.exec("failing").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { .. }));
+
+    // The delay between attempts is cut short by the task's time limit.
+    let start = Instant::now();
+    let err = project.exec("out-of-time").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::TimedOut { .. }));
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::error::Error;
+
+use common::TestProject;
+use rhiz::executor::ErrorKind;
+
+#[test]
+fn test_error_kinds() {
+    let project = TestProject::new(
+        "rhiz-test-errors",
+        r#"
+        (task "arity" (log "a" "b"))
+        (task "type" (log (a)))
+        (task "function" (frobnicate))
+        (task "missing" (copy "missing" "target"))
+        (task "io" (delete "subdir"))
+        "#,
+    );
+    project.create_dirs(&["subdir"]);
+
+    let err = project.exec("unknown").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::UnknownTask(t) if t == "unknown"));
+
+    let err = project.exec("arity").unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::Arity {
+            min: 1,
+            max: Some(1),
+            ..
+        }
+    ));
+    assert_eq!(err.position().unwrap().line, 2);
+
+    let err = project.exec("type").unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::ArgumentType { index: 0, .. }
+    ));
+
+    let err = project.exec("function").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::UnknownFunction(f) if f == "frobnicate"));
+
+    let err = project.exec("missing").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { path, .. } if path.ends_with("missing")));
+
+    let err = project.exec("io").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Io { path: Some(p), .. } if p.ends_with("subdir")));
+    assert!(err.source().is_some());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_failure() {
+    let project = TestProject::new(
+        "rhiz-test-command-failure",
+        r#"
+        (task "fail" (exec sh -c "exit 3"))
+        (task "not-found" (exec rhiz-no-such-command))
+        "#,
+    );
+
+    let err = project.exec("fail").unwrap_err();
+    match err.kind() {
+        ErrorKind::CommandFailed { command, code, .. } => {
+            assert_eq!(command, "sh -c 'exit 3'");
+            assert_eq!(*code, Some(3));
+        }
+        kind => panic!("Unexpected error: {}", kind),
+    }
+
+    let err = project.exec("not-found").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Spawn { .. }));
+    assert!(err.source().is_some());
+}
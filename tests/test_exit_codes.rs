@@ -0,0 +1,33 @@
+mod common;
+
+use common::TestProject;
+
+#[cfg(unix)]
+#[test]
+fn test_exit_codes() {
+    let project = TestProject::new(
+        "rhiz-test-exit-codes",
+        r#"
+        (task "ok" (log "ok"))
+        (task "fail" (exec sh -c "exit 3"))
+        (task "killed" (exec sh -c "kill -9 $$$$"))
+        (task "not-found" (exec rhiz-no-such-command))
+        (task "timeout" (exec :timeout 100ms sh -c "sleep 5"))
+        "#,
+    );
+    let exit_code = |args: &[&str]| project.rhiz(args).status.code();
+
+    assert_eq!(exit_code(&["ok"]), Some(0));
+    assert_eq!(exit_code(&["fail"]), Some(3));
+    assert_eq!(exit_code(&["killed"]), Some(128 + 9));
+    assert_eq!(exit_code(&["not-found"]), Some(127));
+    assert_eq!(exit_code(&["timeout"]), Some(124));
+    assert_eq!(exit_code(&["missing"]), Some(67));
+    assert_eq!(exit_code(&["--unknown-option"]), Some(64));
+
+    project.write("Rhizfile", r#"(task "unclosed""#);
+    assert_eq!(exit_code(&["ok"]), Some(65));
+
+    project.write("Rhizfile", r#"(task "a" (needs b))"#);
+    assert_eq!(exit_code(&["a"]), Some(68));
+}
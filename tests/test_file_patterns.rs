@@ -0,0 +1,100 @@
+mod common;
+
+use common::TestProject;
+use rhiz::executor::ErrorKind;
+
+#[test]
+fn test_file_patterns() {
+    let project = TestProject::new(
+        "rhiz-test-file-patterns",
+        r#"
+        (task "copy-maps" (copy "dist/*.map" "maps"))
+        (task "move-maps" (move "maps/*.map" "moved"))
+        (task "rename" (move "dist/a.js" "dist/app.js"))
+        (task "delete-tmp" (delete "**/*.tmp"))
+        (task "delete-nothing" (delete "**/*.tmp"))
+        (task "must-match" (delete :must-match "**/*.tmp"))
+        (task "not-a-dir" (copy "moved/*.map" "dist/app.js"))
+        "#,
+    );
+    project.create_dirs(&["maps", "moved"]);
+    for file in &[
+        "dist/a.js",
+        "dist/a.map",
+        "dist/b.map",
+        "dist/nested/c.tmp",
+        "d.tmp",
+    ] {
+        project.write(file, file);
+    }
+
+    project.exec("copy-maps").unwrap();
+    assert!(project.path("maps/a.map").is_file());
+    assert!(project.path("maps/b.map").is_file());
+    assert!(project.path("dist/a.map").is_file());
+
+    project.exec("move-maps").unwrap();
+    assert!(project.path("moved/a.map").is_file());
+    assert!(project.path("moved/b.map").is_file());
+    assert!(!project.path("maps/a.map").exists());
+
+    project.exec("rename").unwrap();
+    assert_eq!(project.read("dist/app.js"), "dist/a.js");
+    assert!(!project.path("dist/a.js").exists());
+
+    project.exec("delete-tmp").unwrap();
+    assert!(!project.path("d.tmp").exists());
+    assert!(!project.path("dist/nested/c.tmp").exists());
+
+    // Matching nothing is fine, unless the pattern must match.
+    project.exec("delete-nothing").unwrap();
+    let err = project.exec("must-match").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { .. }));
+
+    // Several files can only be copied into a directory.
+    let err = project.exec("not-a-dir").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { path, .. } if path.ends_with("app.js")));
+}
+
+#[test]
+fn test_literal_paths_that_look_like_patterns() {
+    let project = TestProject::new(
+        "rhiz-test-literal-patterns",
+        r#"
+        (task "copy-bracketed" (copy "a[1].txt" "copied.txt"))
+        (task "delete-bracketed" (delete "a[1].txt"))
+        "#,
+    );
+    project.write("a[1].txt", "bracketed");
+    project.write("a1.txt", "plain");
+
+    project.exec("copy-bracketed").unwrap();
+    assert_eq!(project.read("copied.txt"), "bracketed");
+
+    project.exec("delete-bracketed").unwrap();
+    assert!(!project.path("a[1].txt").exists());
+    assert!(project.path("a1.txt").exists());
+
+    // Once there's no such file, it's a pattern again.
+    project.exec("delete-bracketed").unwrap();
+    assert!(!project.path("a1.txt").exists());
+}
+
+#[test]
+fn test_patterns_skip_dotfiles_and_rhizfile() {
+    let project = TestProject::new(
+        "rhiz-test-file-patterns-dotfiles",
+        r#"(task "clean" (delete :recursive "*"))"#,
+    );
+    project.write(".hidden", ".hidden");
+    project.write(".git/HEAD", "ref: refs/heads/main");
+    project.write("build/.cache", "build/.cache");
+    project.write("output.txt", "output.txt");
+
+    project.exec("clean").unwrap();
+    assert!(!project.path("output.txt").exists());
+    assert!(!project.path("build").exists());
+    assert!(project.path(".hidden").is_file());
+    assert!(project.path(".git/HEAD").is_file());
+    assert!(project.path("Rhizfile").is_file());
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use std::fs;
+
+use common::TestProject;
+use rhiz::cache::Cache;
+
+#[test]
+fn test_fingerprinted_tasks_are_skipped() {
+    // `copy` refuses to clobber its target, so the task fails if it's re-run
+    // without the output being deleted.
+    let project = TestProject::new(
+        "rhiz-test-fingerprint",
+        r#"
+        (task "build"
+          (fingerprint)
+          (sources "src/*.txt")
+          (copy "src/a.txt" "result"))
+        "#,
+    );
+    project.write("src/a.txt", "test contents");
+
+    project.exec("build").unwrap();
+    assert!(Cache::load(&project.dir).unwrap().get("build").is_some());
+    project.exec("build").unwrap();
+
+    // Changing a source's contents makes the task run again.
+    project.write("src/a.txt", "new contents");
+    assert!(project.exec("build").is_err());
+    fs::remove_file(project.path("result")).unwrap();
+    project.exec("build").unwrap();
+    project.exec("build").unwrap();
+
+    // So does clearing the cache.
+    Cache::clear(&project.dir).unwrap();
+    assert!(project.exec("build").is_err());
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use common::TestProject;
+
+#[test]
+fn test_up_to_date_tasks_are_skipped() {
+    // `copy` refuses to clobber its target, so the task fails if it's re-run.
+    let project = TestProject::new(
+        "rhiz-test-incremental",
+        r#"
+        (define out "out")
+        (task "build"
+          (sources "src/*.txt")
+          (outputs "${out}/result")
+          (empty-dir ${out})
+          (copy "src/a.txt" "${out}/result")
+          (copy "src/a.txt" "${out}/result"))
+        "#,
+    );
+    project.write("src/a.txt", "test contents");
+
+    // The first copy succeeds and the second fails, leaving a fresh output.
+    assert!(project.exec("build").is_err());
+    assert!(project.path("out/result").exists());
+    project.exec("build").unwrap();
+
+    let later = SystemTime::now() + Duration::from_secs(60);
+    fs::File::options()
+        .write(true)
+        .open(project.path("src/a.txt"))
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+    assert!(project.exec("build").is_err());
+}
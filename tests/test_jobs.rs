@@ -0,0 +1,33 @@
+mod common;
+
+use common::TestProject;
+use rhiz::executor::Options;
+
+/// Each branch holds a lock directory while its command runs, so a branch
+/// fails if another one's command is running at the same time.
+#[cfg(unix)]
+#[test]
+fn test_job_limits() {
+    let project = TestProject::new(
+        "rhiz-test-jobs",
+        r#"
+        (define locked "mkdir lock && sleep 0.1 && rmdir lock")
+        (task "par-limited"
+          (par :jobs 1
+            (exec sh -c ${locked})
+            (exec sh -c ${locked})
+            (par (exec sh -c ${locked}) (exec sh -c ${locked}))))
+        (task "par" (par (exec sh -c ${locked}) (exec sh -c ${locked})))
+        (task "bad-jobs" (par :jobs none (log "a")))
+        "#,
+    );
+
+    project.exec("par-limited").unwrap();
+    assert!(project.exec("bad-jobs").is_err());
+
+    let options = Options {
+        jobs: Some(1),
+        ..Options::default()
+    };
+    project.exec_tasks(&["par"], &options).unwrap();
+}
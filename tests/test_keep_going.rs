@@ -0,0 +1,44 @@
+mod common;
+
+use common::TestProject;
+use rhiz::executor::{ErrorKind, Options};
+
+#[test]
+fn test_keep_going() {
+    let project = TestProject::new(
+        "rhiz-test-keep-going",
+        r#"
+        (task "fail-a" (copy "missing-a" "a"))
+        (task "fail-b" (copy "missing-b" "b"))
+        (task "ok" (empty-dir "ok"))
+        (task "needs-fail" (needs fail-a ok) (empty-dir "not-reached"))
+        (task "par" (par (copy "missing-c" "c") (empty-dir "par-ok") (copy "missing-d" "d")))
+        "#,
+    );
+    let error_count = |kind: &ErrorKind| match kind {
+        ErrorKind::Multiple(errors) => errors.len(),
+        _ => 1,
+    };
+
+    // Without keep-going, the run stops at the first failure.
+    let tasks = ["fail-a", "fail-b", "needs-fail"];
+    let err = project.exec_tasks(&tasks, &Options::default()).unwrap_err();
+    assert_eq!(error_count(err.kind()), 1);
+    assert!(!project.path("ok").exists());
+
+    // With it, every task which doesn't need a failed one is run, and each
+    // failure is reported once.
+    let options = Options {
+        keep_going: true,
+        ..Options::default()
+    };
+    let err = project.exec_tasks(&tasks, &options).unwrap_err();
+    assert_eq!(error_count(err.kind()), 2, "{:?}", err);
+    assert!(project.path("ok").exists());
+    assert!(!project.path("not-reached").exists());
+
+    // `par` reports every branch's error.
+    let err = project.exec("par").unwrap_err();
+    assert_eq!(error_count(err.kind()), 2, "{:?}", err);
+    assert!(project.path("par-ok").exists());
+}
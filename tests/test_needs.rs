@@ -0,0 +1,85 @@
+mod common;
+
+use common::TestProject;
+use rhiz::executor::Options;
+
+#[test]
+fn test_needs_run_once_and_first() {
+    // `copy` refuses to clobber its target, so "setup" fails if it runs twice
+    // and "use" fails if it runs before "setup".
+    let project = TestProject::new(
+        "rhiz-test-needs",
+        r#"
+        (task "setup" (copy "source" "copied"))
+        (task "left" (needs setup) (copy "copied" "left"))
+        (task "right" (needs setup) (copy "copied" "right"))
+        (task "all" (needs left right))
+        "#,
+    );
+    project.write("source", "test contents");
+
+    project.exec("all").unwrap();
+
+    assert!(project.path("left").exists());
+    assert!(project.path("right").exists());
+}
+
+#[test]
+fn test_multiple_tasks() {
+    let project = TestProject::new(
+        "rhiz-test-multiple-tasks",
+        r#"
+        (task "setup" (copy "source" "copied"))
+        (task "left" (needs setup) (copy "copied" "left"))
+        (task "right" (needs setup) (copy "copied" "right"))
+        (task "broken" (copy "missing" "broken"))
+        (task "after" (copy "source" "after"))
+        "#,
+    );
+    project.write("source", "test contents");
+
+    let parallel = Options {
+        parallel: true,
+        ..Options::default()
+    };
+    project.exec_tasks(&["left", "right"], &parallel).unwrap();
+    assert!(project.path("left").exists());
+    assert!(project.path("right").exists());
+
+    let sequential = Options::default();
+    assert!(project
+        .exec_tasks(&["broken", "after"], &sequential)
+        .is_err());
+    assert!(!project.path("after").exists());
+}
+
+#[test]
+fn test_many_parallel_tasks_sharing_needs() {
+    // More tasks than there are CPUs, all waiting on the same needs while
+    // one of them runs them.
+    let mut src = String::from(
+        r#"
+        (task "setup" (copy "source" "copied"))
+        (task "more-setup" (needs setup) (copy "copied" "copied-again"))
+        "#,
+    );
+    let mut tasks = Vec::new();
+    for i in 0..64 {
+        let name = format!("task-{}", i);
+        src.push_str(&format!(
+            r#"(task "{0}" (needs more-setup) (copy "copied-again" "{0}"))"#,
+            name
+        ));
+        tasks.push(name);
+    }
+    let project = TestProject::new("rhiz-test-parallel-needs", &src);
+    project.write("source", "test contents");
+
+    let parallel = Options {
+        parallel: true,
+        ..Options::default()
+    };
+    let tasks: Vec<&str> = tasks.iter().map(String::as_str).collect();
+    project.exec_tasks(&tasks, &parallel).unwrap();
+    assert!(project.path("task-63").exists());
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::TestProject;
+
+#[cfg(unix)]
+#[test]
+fn test_parallel_output() {
+    let project = TestProject::new(
+        "rhiz-test-output",
+        r#"
+        (task "lint" (exec sh -c "echo linted; echo more"))
+        (task "test" (log "tested"))
+        (task "par-block"
+          (par :output block
+            (exec sh -c "echo one; sleep 0.1; echo two")
+            (exec sh -c "echo three; echo four")
+            (sh "echo five")))
+        "#,
+    );
+    let stdout = |args: &[&str]| {
+        let output = project.rhiz(args);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let output = stdout(&["--output", "prefix", "--parallel", "lint", "test"]);
+    let mut lines: Vec<&str> = output.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(lines, vec!["[lint] linted", "[lint] more", "[test] tested"]);
+
+    // Each branch's lines are kept together.
+    let output = stdout(&["par-block"]);
+    let mut blocks: Vec<&str> = output.split('[').skip(1).map(str::trim_end).collect();
+    blocks.sort_unstable();
+    assert_eq!(
+        blocks,
+        vec!["echo]\nfive", "sh 1]\none\ntwo", "sh 2]\nthree\nfour"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_background_process_does_not_hold_up_branch() {
+    // The background `sleep` keeps the branch's output open after `sh` exits.
+    let project = TestProject::new(
+        "rhiz-test-output-background",
+        r#"
+        (task "par-background"
+          (par :output prefix
+            (sh "sleep 10 & echo started")
+            (log "other")))
+        "#,
+    );
+
+    let start = std::time::Instant::now();
+    let output = project.rhiz(&["par-background"]);
+    assert!(output.status.success());
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().any(|l| l.ends_with("] started")));
+}
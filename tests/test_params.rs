@@ -0,0 +1,38 @@
+mod common;
+
+use common::TestProject;
+
+#[test]
+fn test_params_from_arguments() {
+    let project = TestProject::new(
+        "rhiz-test-params",
+        r#"
+        (task "place"
+          (params dest (name "copied"))
+          (empty-dir ${dest})
+          (copy "source" "${dest}/${name}"))
+        (task "needs-place" (needs place))
+        "#,
+    );
+    project.write("source", "test contents");
+
+    project.exec_with_args("place", &["one"]).unwrap();
+    assert!(project.path("one/copied").exists());
+
+    project
+        .exec_with_args("place", &["name=renamed", "two"])
+        .unwrap();
+    assert!(project.path("two/renamed").exists());
+
+    project
+        .exec_with_args("place", &["dest=three", "other"])
+        .unwrap();
+    assert!(project.path("three/other").exists());
+
+    let err_cases: &[&[&str]] = &[&[], &["a", "b", "c"], &["target=a"]];
+    for case in err_cases {
+        assert!(project.exec_with_args("place", case).is_err());
+    }
+    // Missing parameters of needed tasks are reported too.
+    assert!(project.exec("needs-place").is_err());
+}
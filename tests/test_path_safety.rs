@@ -0,0 +1,130 @@
+mod common;
+
+use std::fs;
+
+use common::TestProject;
+use rhiz::executor::ErrorKind;
+
+#[test]
+fn test_outside_paths() {
+    let project = TestProject::new(
+        "rhiz-test-outside-paths",
+        r#"
+        (task "empty-root" (empty-dir "/"))
+        (task "empty-self" (empty-dir "."))
+        (task "empty-outside" (empty-dir "../outside"))
+        (task "delete-outside" (delete "../outside/keep.txt"))
+        (task "move-outside" (move "build/a.txt" "../outside"))
+        (task "rec-copy-outside" (rec-copy "build" "../outside"))
+        (task "allowed" (move :allow-outside "build/a.txt" "../outside"))
+        "#,
+    );
+    let outside = project.root.join("outside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("keep.txt"), "keep").unwrap();
+    project.write("build/a.txt", "a");
+
+    for task in &[
+        "empty-root",
+        "empty-self",
+        "empty-outside",
+        "delete-outside",
+        "move-outside",
+        "rec-copy-outside",
+    ] {
+        let err = project.exec(task).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::BadPath { .. }), "{}", task);
+    }
+    assert!(outside.join("keep.txt").exists());
+    assert!(project.path("build/a.txt").exists());
+
+    project.exec("allowed").unwrap();
+    assert!(outside.join("a.txt").exists());
+}
+
+#[test]
+fn test_repository_paths() {
+    // The project is a subdirectory of a repository, next to another one.
+    let project = TestProject::new(
+        "rhiz-test-repository-paths",
+        r#"
+        (task "delete-dir-sibling" (delete-dir "../sibling"))
+        (task "delete-recursive-sibling" (delete :recursive "../sibling"))
+        (task "empty-sibling" (empty-dir "../sibling"))
+        (task "delete-sibling-file" (delete "../sibling/old.txt"))
+        "#,
+    );
+    let sibling = project.root.join("sibling");
+    fs::create_dir_all(project.root.join(".git")).unwrap();
+    fs::create_dir_all(&sibling).unwrap();
+    fs::write(sibling.join("keep.txt"), "keep").unwrap();
+    fs::write(sibling.join("old.txt"), "old").unwrap();
+
+    // Deleting whole directories stays inside the Rhizfile's directory...
+    for task in &[
+        "delete-dir-sibling",
+        "delete-recursive-sibling",
+        "empty-sibling",
+    ] {
+        let err = project.exec(task).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::BadPath { .. }), "{}", task);
+    }
+    assert!(sibling.join("keep.txt").exists());
+
+    // ...but a file elsewhere in the repository can be deleted.
+    project.exec("delete-sibling-file").unwrap();
+    assert!(!sibling.join("old.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinks_are_not_followed() {
+    use std::os::unix::fs::symlink;
+
+    let project = TestProject::new(
+        "rhiz-test-symlinks",
+        r#"
+        (task "empty-cache" (empty-dir "cache"))
+        (task "empty-link" (empty-dir "link"))
+        (task "delete-link" (delete-dir "link"))
+        (task "through-link" (delete "other-link/keep.txt"))
+        (task "rec-copy-link" (rec-copy "src" "link"))
+        (task "rec-copy-onto-link" (rec-copy "src" "dist"))
+        "#,
+    );
+    let outside = project.root.join("outside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("keep.txt"), "keep").unwrap();
+    project.create_dirs(&["cache", "dist"]);
+    project.write("src/keep.txt", "overwritten");
+    symlink(&outside, project.path("link")).unwrap();
+    symlink(&outside, project.path("cache/link")).unwrap();
+    symlink(&outside, project.path("other-link")).unwrap();
+    symlink(outside.join("keep.txt"), project.path("dist/keep.txt")).unwrap();
+
+    // The links are removed, but not what they point to.
+    project.exec("empty-cache").unwrap();
+    assert!(fs::symlink_metadata(project.path("cache/link")).is_err());
+
+    let err = project.exec("empty-link").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { .. }));
+
+    project.exec("delete-link").unwrap();
+    assert!(fs::symlink_metadata(project.path("link")).is_err());
+
+    // A path through a link resolves to where the link points.
+    let err = project.exec("through-link").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { .. }));
+
+    // Nor does `rec-copy` write through a link.
+    symlink(&outside, project.path("link")).unwrap();
+    for task in &["rec-copy-link", "rec-copy-onto-link"] {
+        let err = project.exec(task).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::BadPath { .. }), "{}", task);
+    }
+
+    assert_eq!(
+        fs::read_to_string(outside.join("keep.txt")).unwrap(),
+        "keep"
+    );
+}
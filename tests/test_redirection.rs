@@ -0,0 +1,59 @@
+mod common;
+
+use std::fs;
+
+use common::TestProject;
+use rhiz::executor::{ErrorKind, Options};
+
+#[cfg(unix)]
+#[test]
+fn test_redirection() {
+    let project = TestProject::new(
+        "rhiz-test-redirection",
+        r#"
+        (task "files"
+          (exec :stdout "log.txt" echo one)
+          (exec :stdout-append "log.txt" echo two)
+          (sh :stderr "err.txt" :stdout :null "echo hidden; echo error >&2")
+          (exec :stdin "input.txt" :stdout "copied.txt" cat)
+          (exec :input "literal text" :stdout "literal.txt" cat))
+        (task "capture"
+          (exec :capture version echo "v1.2.3")
+          (exec :stdout "version.txt" echo "version ${version}"))
+        (task "conflict" (exec :capture out :stdout "a.txt" echo a))
+        (task "missing-input" (exec :stdin "missing.txt" cat))
+        "#,
+    );
+    project.write("input.txt", "from a file\n");
+
+    project.exec("files").unwrap();
+    assert_eq!(project.read("log.txt"), "one\ntwo\n");
+    assert_eq!(project.read("err.txt"), "error\n");
+    assert_eq!(project.read("copied.txt"), "from a file\n");
+    assert_eq!(project.read("literal.txt"), "literal text");
+
+    // Running it again truncates the file rather than appending to it.
+    project.exec("files").unwrap();
+    assert_eq!(project.read("log.txt"), "one\ntwo\n");
+
+    project.exec("capture").unwrap();
+    assert_eq!(project.read("version.txt"), "version v1.2.3\n");
+
+    let err = project.exec("conflict").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Invalid(_)));
+
+    let err = project.exec("missing-input").unwrap_err();
+    assert!(
+        matches!(err.kind(), ErrorKind::Io { path: Some(p), .. } if p.ends_with("missing.txt"))
+    );
+
+    // In a dry run, later commands refer to the output which would be
+    // captured.
+    fs::remove_file(project.path("version.txt")).unwrap();
+    let options = Options {
+        dry_run: true,
+        ..Options::default()
+    };
+    project.exec_tasks(&["capture"], &options).unwrap();
+    assert!(!project.path("version.txt").exists());
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::TestProject;
+use rhiz::executor::{ErrorKind, Options};
+
+#[cfg(unix)]
+#[test]
+fn test_shell_scripts() {
+    let project = TestProject::new(
+        "rhiz-test-shell",
+        r#"
+        (define greeting "hello")
+        (task "pipe" (sh "echo ${greeting} | tr h j > out.txt && echo $$HOME >> out.txt"))
+        (task "env" (sh :env NAME=world "echo $$NAME > env.txt"))
+        (task "option" (sh :shell "sh -ec" "false; echo not-reached > option.txt"))
+        (task "fail" (sh "exit 4"))
+        (task "shell-name" (sh "echo $$0 > shell.txt"))
+        "#,
+    );
+
+    project.exec("pipe").unwrap();
+    let home = std::env::var("HOME").unwrap();
+    assert_eq!(project.read("out.txt"), format!("jello\n{}\n", home));
+
+    project.exec("env").unwrap();
+    assert_eq!(project.read("env.txt"), "world\n");
+
+    assert!(project.exec("option").is_err());
+    assert!(!project.path("option.txt").exists());
+
+    let err = project.exec("fail").unwrap_err();
+    match err.kind() {
+        ErrorKind::CommandFailed { command, code, .. } => {
+            assert_eq!(command, "/bin/sh -c 'exit 4'");
+            assert_eq!(*code, Some(4));
+        }
+        kind => panic!("Unexpected error: {}", kind),
+    }
+
+    // The shell for the whole run can be changed.
+    let options = Options {
+        shell: Some("sh -c".to_owned()),
+        ..Options::default()
+    };
+    project.exec_tasks(&["shell-name"], &options).unwrap();
+    assert_eq!(project.read("shell.txt"), "sh\n");
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::TestProject;
+use rhiz::executor::{ErrorKind, Options, OutputMode};
+
+#[cfg(unix)]
+#[test]
+fn test_timeouts() {
+    let project = TestProject::new(
+        "rhiz-test-timeouts",
+        r#"
+        (task "command" (exec :timeout 200ms sh -c "sleep 5"))
+        (task "quick" (exec :timeout 5s sh -c "exit 0"))
+        (task "task"
+          (timeout 300ms)
+          (exec sh -c "sleep 0.1")
+          (exec sh -c "sleep 5")
+          (finally (empty-dir "cleaned-up")))
+        (task "group"
+          (par (exec :timeout 200ms sh -c "sleep 5 & wait")))
+        (task "bad-limit"
+          (params (limit soon))
+          (timeout ${limit})
+          (finally (empty-dir "cleaned-up-anyway")))
+        "#,
+    );
+
+    let start = Instant::now();
+    let err = project.exec("command").unwrap_err();
+    match err.kind() {
+        ErrorKind::TimedOut { what, limit } => {
+            assert_eq!(what, "Command 'sh -c 'sleep 5''");
+            assert_eq!(*limit, Duration::from_millis(200));
+        }
+        kind => panic!("Unexpected error: {}", kind),
+    }
+    assert!(start.elapsed() < Duration::from_secs(3));
+
+    project.exec("quick").unwrap();
+
+    let start = Instant::now();
+    let err = project.exec("task").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::TimedOut { what, .. } if what == "Task 'task'"));
+    assert!(start.elapsed() < Duration::from_secs(3));
+    assert!(project.path("cleaned-up").is_dir());
+
+    // An invalid limit fails the task, but its `finally` still runs.
+    let err = project.exec("bad-limit").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Invalid(_)));
+    assert!(project.path("cleaned-up-anyway").is_dir());
+
+    // The backgrounded `sleep` holds the output pipe open until it's killed
+    // along with the rest of the command's process group.
+    let options = Options {
+        output: OutputMode::Prefixed,
+        ..Options::default()
+    };
+    let start = Instant::now();
+    let err = project.exec_tasks(&["group"], &options).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::TimedOut { .. }));
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_interrupt_reaches_commands_with_timeouts() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+    use std::thread;
+
+    let project = TestProject::new(
+        "rhiz-test-interrupt-timeouts",
+        r#"
+        (task "wait"
+          (exec :timeout 30s sh -c
+            "trap 'touch interrupted; exit 1' INT; touch started; i=0; while [ $$i -lt 200 ]; do sleep 0.05; i=$$((i + 1)); done"))
+        "#,
+    );
+
+    let mut rhiz = Command::new(env!("CARGO_BIN_EXE_rhiz"))
+        .arg("wait")
+        .current_dir(&project.dir)
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while !project.path("started").exists() {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(20));
+    }
+    let status = Command::new("kill")
+        .args(["-INT", &rhiz.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // rhiz passes the interrupt on to the command's process group, then stops.
+    let status = rhiz.wait().unwrap();
+    assert_eq!(status.signal(), Some(2));
+    let start = Instant::now();
+    while !project.path("interrupted").exists() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(20));
+    }
+}
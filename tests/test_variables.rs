@@ -0,0 +1,25 @@
+mod common;
+
+use common::TestProject;
+
+#[test]
+fn test_defined_variables_are_substituted() {
+    let project = TestProject::new(
+        "rhiz-test-variables",
+        r#"
+        (define dist out/dist)
+        (define name "copied")
+        (task "package"
+          (empty-dir ${dist})
+          (copy "source" "${dist}/${name}"))
+        (task "broken"
+          (copy "source" "${missing}"))
+        "#,
+    );
+    project.write("source", "test contents");
+
+    project.exec("package").unwrap();
+    assert!(project.path("out/dist/copied").exists());
+
+    assert!(project.exec("broken").is_err());
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::TestProject;
+use rhiz::executor::{ErrorKind, Options};
+
+#[test]
+fn test_in_dir() {
+    let project = TestProject::new(
+        "rhiz-test-in-dir",
+        r#"
+        (task "nested"
+          (in-dir "frontend"
+            (empty-dir "dist")
+            (in-dir "src" (empty-dir "generated")))
+          (empty-dir "root"))
+        (task "missing" (in-dir "backend" (empty-dir "not-reached")))
+        "#,
+    );
+    project.create_dirs(&["frontend/src"]);
+
+    project.exec("nested").unwrap();
+    assert!(project.path("frontend/dist").is_dir());
+    assert!(project.path("frontend/src/generated").is_dir());
+    assert!(project.path("root").is_dir());
+
+    let err = project.exec("missing").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { path, .. } if path.ends_with("backend")));
+    assert!(!project.path("backend").exists());
+}
+
+#[test]
+fn test_in_dir_dry_run() {
+    // The directory `in-dir` goes into would only be made by the real run.
+    let project = TestProject::new(
+        "rhiz-test-in-dir-dry-run",
+        r#"
+        (task "gen"
+          (empty-dir "gen")
+          (in-dir "gen"
+            (empty-dir "out")
+            (in-dir "out" (delete "stale") (copy "a" "b") (rec-copy "c" "d"))))
+        "#,
+    );
+    let options = Options {
+        dry_run: true,
+        ..Options::default()
+    };
+
+    project.exec_tasks(&["gen"], &options).unwrap();
+    assert!(!project.path("gen").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_in_dir_deleted() {
+    let project = TestProject::new(
+        "rhiz-test-in-dir-deleted",
+        r#"(task "gen" (in-dir "gen" (sh "rm -r ../gen") (empty-dir "out")))"#,
+    );
+    project.create_dirs(&["gen"]);
+
+    let err = project.exec("gen").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::BadPath { path, .. } if path.ends_with("gen")));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec_cwd() {
+    let project = TestProject::new(
+        "rhiz-test-exec-cwd",
+        r#"
+        (task "cwd"
+          (exec :cwd "docs" :stdout "where.txt" pwd)
+          (in-dir "docs"
+            (sh :cwd "book" "pwd > where.txt")))
+        "#,
+    );
+    project.create_dirs(&["docs/book"]);
+
+    project.exec("cwd").unwrap();
+    assert!(project.read("docs/where.txt").trim_end().ends_with("docs"));
+    assert!(project
+        .read("docs/book/where.txt")
+        .trim_end()
+        .ends_with("docs/book"));
+}